@@ -20,13 +20,19 @@ pub struct Env {
     pub url_returning_200: String,
     /// Whether the connection should be encrypted
     pub encrypted: bool,
+    /// An optional url which returns a large response
+    pub url_large_resource: Option<String>,
+    /// An optional url which accepts request bodies
+    pub url_body_target: Option<String>,
+    /// An optional url which accepts file uploads
+    pub url_upload: Option<String>,
 }
 
 impl Env {
     /// Creats the `Enc` structure using environment variables
     ///
     /// # Errors
-    /// Fails if one or more env variables are missing
+    /// Fails if one or more required env variables are missing
     #[inline]
     pub fn new() -> Result<Self> {
         Ok(Self {
@@ -37,6 +43,13 @@ impl Env {
                 .context("ENCRYPTED must be set")?
                 .parse()
                 .context("Unable to convert ENCRYPTED into bool")?,
+            url_large_resource: optional_var("URL_LARGE_RESOURCE"),
+            url_body_target: optional_var("URL_BODY_TARGET"),
+            url_upload: optional_var("URL_UPLOAD"),
         })
     }
 }
+
+fn optional_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
@@ -1,6 +1,35 @@
-use crate::{write, Result};
-use futures::io::AsyncWriteExt;
-use std::fmt::Display;
+use crate::{env::Env, read, tcp::connect, timeout, write, Result};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::{
+    fmt::Display,
+    str,
+    time::{Duration, Instant},
+};
+
+/// Time a server gets to answer a request
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time a server gets to answer the liveness probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the server answered a request
+#[derive(Debug, Clone, Copy)]
+pub enum Status {
+    /// Status code of the response line
+    Code(u16),
+    /// Connection was closed without a response
+    Aborted,
+    /// No response within `RESPONSE_TIMEOUT`
+    TimedOut,
+}
+
+/// Reaction of the server to a request
+#[derive(Debug, Clone, Copy)]
+pub struct Response {
+    /// How the server answered
+    pub status: Status,
+    /// Time from the first body byte until the status line arrives
+    pub elapsed: Duration,
+}
 
 /// Writes the http message using the given url
 ///
@@ -14,6 +43,18 @@ pub async fn write_message<S: AsyncWriteExt + Unpin, D: Display>(
     write(stream, format!("GET {} HTTP/1.0\n", url).as_bytes()).await
 }
 
+/// Writes the http post message using the given url
+///
+/// # Errors
+/// Fails if the OS is unable to write data to the given stream
+#[inline]
+pub async fn write_post_message<S: AsyncWriteExt + Unpin, D: Display>(
+    stream: &mut S,
+    url: &D,
+) -> Result<()> {
+    write(stream, format!("POST {} HTTP/1.0\n", url).as_bytes()).await
+}
+
 /// Writes the http host header using the given fqdn and its port
 ///
 /// # Errors
@@ -47,6 +88,38 @@ pub async fn write_content_length<S: AsyncWriteExt + Unpin>(
     write(stream, format!("Content-Length: {}\n", length).as_bytes()).await
 }
 
+/// Writes the http content type header
+///
+/// # Errors
+/// Fails if the OS is unable to write data to the given stream
+#[inline]
+pub async fn write_content_type<S: AsyncWriteExt + Unpin, D: Display>(
+    stream: &mut S,
+    content_type: &D,
+) -> Result<()> {
+    write(
+        stream,
+        format!("Content-Type: {}\n", content_type).as_bytes(),
+    )
+    .await
+}
+
+/// Writes the http content encoding header
+///
+/// # Errors
+/// Fails if the OS is unable to write data to the given stream
+#[inline]
+pub async fn write_content_encoding<S: AsyncWriteExt + Unpin, D: Display>(
+    stream: &mut S,
+    encoding: &D,
+) -> Result<()> {
+    write(
+        stream,
+        format!("Content-Encoding: {}\n", encoding).as_bytes(),
+    )
+    .await
+}
+
 /// Ends the http header
 ///
 /// # Errors
@@ -68,3 +141,136 @@ pub async fn write_body<S: AsyncWriteExt + Unpin, B: AsRef<[u8]>>(
     let data: &[u8] = data.as_ref();
     write(stream, data).await
 }
+
+/// Returns the position right after the end of the http header
+/// if the given data already contains it
+#[inline]
+#[must_use]
+pub fn find_header_end(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos.saturating_add(4))
+        .or_else(|| {
+            data.windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|pos| pos.saturating_add(2))
+        })
+}
+
+/// Extracts the status code from the status line of a http response
+#[inline]
+#[must_use]
+pub fn parse_status_code(head: &[u8]) -> Option<u16> {
+    let line = head.split(|byte| *byte == b'\n').next()?;
+    let line = str::from_utf8(line).ok()?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Extracts the value of the given header from a http response head
+#[inline]
+#[must_use]
+pub fn parse_header<'head>(head: &'head [u8], name: &str) -> Option<&'head str> {
+    let head = str::from_utf8(head).ok()?;
+    head.lines().skip(1).find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next()?.trim();
+        let value = parts.next()?.trim();
+        if key.eq_ignore_ascii_case(name) {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Extracts the `Content-Length` from a http response head
+#[inline]
+#[must_use]
+pub fn parse_content_length(head: &[u8]) -> Option<usize> {
+    parse_header(head, "Content-Length")?.parse().ok()
+}
+
+/// Reads the http status line and extracts its status code
+///
+/// # Errors
+/// Fails if the OS is unable to read data from the given stream
+#[inline]
+pub async fn read_status_code<S: AsyncReadExt + Unpin>(stream: &mut S) -> Result<Option<u16>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    loop {
+        let size = read(stream, &mut buffer).await?;
+        if size == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..size]);
+        if head.contains(&b'\n') {
+            break;
+        }
+    }
+    Ok(parse_status_code(&head))
+}
+
+/// Waits at most `RESPONSE_TIMEOUT` for the status line of the response
+#[inline]
+pub async fn read_status<S: AsyncReadExt + Unpin>(stream: &mut S) -> Status {
+    match timeout(RESPONSE_TIMEOUT, read_status_code(stream)).await {
+        Ok(Ok(Some(status))) => Status::Code(status),
+        Ok(_) => Status::Aborted,
+        Err(_) => Status::TimedOut,
+    }
+}
+
+/// Posts the body in a single request to the server of the env and waits
+/// for the response status
+///
+/// # Errors
+/// Fails if the connection to the server could not be established or the
+/// header could not be written
+#[inline]
+pub async fn send_post(
+    env: &Env,
+    url: &str,
+    content_type: &str,
+    content_encoding: Option<&str>,
+    body: &[u8],
+) -> Result<Response> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    write_post_message(&mut stream, &url).await?;
+    write_host(&mut stream, &env.fqdn_with_port).await?;
+    write_user_agent(&mut stream).await?;
+    write_content_type(&mut stream, &content_type).await?;
+    if let Some(encoding) = content_encoding {
+        write_content_encoding(&mut stream, &encoding).await?;
+    }
+    write_content_length(&mut stream, body.len()).await?;
+    write_header_end(&mut stream).await?;
+
+    let start = Instant::now();
+    // Servers may close the connection before the whole body is sent
+    let status = if write_body(&mut stream, body).await.is_ok() {
+        read_status(&mut stream).await
+    } else {
+        Status::Aborted
+    };
+    Ok(Response {
+        status,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Checks whether `URL_RETURING_200` still returns 200 within
+/// `PROBE_TIMEOUT`
+#[inline]
+pub async fn is_alive(env: &Env) -> bool {
+    matches!(timeout(PROBE_TIMEOUT, probe(env)).await, Ok(Ok(Some(200))))
+}
+
+async fn probe(env: &Env) -> Result<Option<u16>> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    write_message(&mut stream, &env.url_returning_200).await?;
+    write_host(&mut stream, &env.fqdn_with_port).await?;
+    write_user_agent(&mut stream).await?;
+    write_header_end(&mut stream).await?;
+    read_status_code(&mut stream).await
+}
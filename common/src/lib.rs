@@ -60,7 +60,8 @@ pub mod http;
 pub mod tcp;
 
 pub use anyhow::{bail, Context, Result};
-pub use futures::io::AsyncWriteExt;
+pub use async_std::{future::timeout, task::sleep};
+pub use futures::io::{AsyncReadExt, AsyncWriteExt};
 
 use std::future::Future;
 
@@ -84,3 +85,15 @@ pub async fn write<S: AsyncWriteExt + Unpin>(stream: &mut S, data: &[u8]) -> Res
         .await
         .context("Unable to write Data to stream")
 }
+
+/// Reads data from the given stream into the given buffer
+///
+/// # Errors
+/// Fails if the OS is unable to read data from the given stream
+#[inline]
+pub async fn read<S: AsyncReadExt + Unpin>(stream: &mut S, buf: &mut [u8]) -> Result<usize> {
+    stream
+        .read(buf)
+        .await
+        .context("Unable to read Data from stream")
+}
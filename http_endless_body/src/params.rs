@@ -0,0 +1,208 @@
+use crate::{classify, send_payload, target_url, Payload};
+use common::{
+    env::Env,
+    http::{self, Response, Status},
+    tcp::{connect, MaybeHttpsStream},
+    write, Result,
+};
+use std::{cmp::max, time::Instant};
+
+const FORM: &str = "application/x-www-form-urlencoded";
+
+const CHUNK_PARAMETERS: usize = 100;
+// Tomcat's default maxParameterCount
+const PARAMETER_LIMIT: usize = 10_000;
+const BODY_COUNTS: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+const QUERY_COUNTS: [usize; 4] = [100, 1_000, 10_000, 100_000];
+// Colliding keys are at least 2 * COLLISION_BITS characters long
+const COLLISION_BITS: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+enum Keys {
+    /// `a0`, `a1`, `a2`, ...
+    Sequential,
+    /// Combinations of `Aa` and `BB` which share the same `String.hashCode`.
+    /// They only collide for Java, other hash functions treat them like any
+    /// other key.
+    Colliding,
+}
+
+impl Keys {
+    const ALL: [Self; 2] = [Self::Sequential, Self::Colliding];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sequential => "sequential",
+            Self::Colliding => "Java colliding",
+        }
+    }
+
+    fn key(self, index: usize) -> String {
+        match self {
+            Self::Sequential => format!("a{}", index),
+            Self::Colliding => {
+                // keys grow once the index needs more bits so they never repeat,
+                // keys of the same length still share one hash
+                let significant = (0_usize.leading_zeros() - index.leading_zeros()) as usize;
+                (0..max(COLLISION_BITS, significant))
+                    .map(|bit| if (index >> bit) & 1 == 0 { "Aa" } else { "BB" })
+                    .collect()
+            }
+        }
+    }
+
+    /// Creates `count` parameters starting with the key at `start`
+    fn parameters(self, start: usize, count: usize) -> String {
+        let mut parameters = String::new();
+        for index in start..start.saturating_add(count) {
+            parameters.push_str(&self.key(index));
+            parameters.push_str("=x&");
+        }
+        parameters
+    }
+}
+
+pub(crate) async fn parameter_floods(env: &Env) -> Result<i32> {
+    let mut code = 0;
+    for keys in &Keys::ALL {
+        code = max(code, endless_parameters(env, *keys).await?);
+        code = max(code, endless_query(env, *keys).await?);
+        code = max(code, body_latency(env, *keys).await?);
+        code = max(code, query_latency(env, *keys).await?);
+    }
+    Ok(code)
+}
+
+async fn endless_parameters(env: &Env, keys: Keys) -> Result<i32> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    http::write_post_message(&mut stream, &target_url(env)).await?;
+    http::write_host(&mut stream, &env.fqdn_with_port).await?;
+    http::write_user_agent(&mut stream).await?;
+    http::write_content_type(&mut stream, &FORM).await?;
+    http::write_content_length(&mut stream, usize::max_value()).await?;
+    http::write_header_end(&mut stream).await?;
+    let total = stream_parameters(&mut stream, keys).await;
+    Ok(report_endless(total, keys, "body"))
+}
+
+/// Sends a request line whose query string never ends
+async fn endless_query(env: &Env, keys: Keys) -> Result<i32> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    let url = target_url(env);
+    let separator = if url.contains('?') { '&' } else { '?' };
+    write(&mut stream, format!("GET {}{}", url, separator).as_bytes()).await?;
+    let total = stream_parameters(&mut stream, keys).await;
+    Ok(report_endless(total, keys, "query"))
+}
+
+/// Writes parameters until the server closes the connection and returns
+/// their count, or `None` if the count exceeds the usize range
+async fn stream_parameters(stream: &mut MaybeHttpsStream, keys: Keys) -> Option<usize> {
+    let mut counter: usize = 0;
+    loop {
+        let parameters = keys.parameters(counter, CHUNK_PARAMETERS);
+        if write(stream, parameters.as_bytes()).await.is_err() {
+            return Some(counter);
+        }
+        counter = counter.checked_add(CHUNK_PARAMETERS)?;
+    }
+}
+
+fn report_endless(total: Option<usize>, keys: Keys, location: &str) -> i32 {
+    match total {
+        Some(total) if total <= PARAMETER_LIMIT => {
+            println!(
+                "Wrote {} {} {} parameters. This looks like a good limit!",
+                total,
+                keys.name(),
+                location
+            );
+            0
+        }
+        Some(total) => {
+            println!(
+                "Wrote {} {} {} parameters. Either you do not have a limit or its very high. You may want to set it to 10_000 or lower!",
+                total,
+                keys.name(),
+                location
+            );
+            1
+        }
+        None => {
+            println!("Aborting as we reached a value outside the usize range while sending parameters. You may want to introduce a limit to your parameter parsing!");
+            2
+        }
+    }
+}
+
+async fn body_latency(env: &Env, keys: Keys) -> Result<i32> {
+    let mut code = 0;
+    let mut latencies = Vec::new();
+    for count in &BODY_COUNTS {
+        let payload = Payload {
+            name: "parameter body",
+            content_type: FORM,
+            content_encoding: None,
+            data: keys.parameters(0, *count).into_bytes(),
+        };
+        let response = send_payload(env, target_url(env), &payload).await?;
+        let name = format!("{} {} body parameters", count, keys.name());
+        code = max(code, classify(env, &name, response).await);
+        latencies.push(response.elapsed.as_millis());
+    }
+    print_growth(
+        &format!("{} body parameters", keys.name()),
+        &BODY_COUNTS,
+        &latencies,
+    );
+    Ok(code)
+}
+
+async fn query_latency(env: &Env, keys: Keys) -> Result<i32> {
+    let mut code = 0;
+    let mut latencies = Vec::new();
+    for count in &QUERY_COUNTS {
+        let url = target_url(env);
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}", url, separator, keys.parameters(0, *count));
+        let response = send_query(env, &url).await?;
+        let name = format!("{} {} query parameters", count, keys.name());
+        code = max(code, classify(env, &name, response).await);
+        latencies.push(response.elapsed.as_millis());
+    }
+    print_growth(
+        &format!("{} query parameters", keys.name()),
+        &QUERY_COUNTS,
+        &latencies,
+    );
+    Ok(code)
+}
+
+async fn send_query(env: &Env, url: &str) -> Result<Response> {
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    let start = Instant::now();
+    // Servers may close the connection while the request line is still being sent
+    let status = if write_query(&mut stream, env, url).await.is_ok() {
+        http::read_status(&mut stream).await
+    } else {
+        Status::Aborted
+    };
+    Ok(Response {
+        status,
+        elapsed: start.elapsed(),
+    })
+}
+
+async fn write_query(stream: &mut MaybeHttpsStream, env: &Env, url: &str) -> Result<()> {
+    http::write_message(stream, &url).await?;
+    http::write_host(stream, &env.fqdn_with_port).await?;
+    http::write_user_agent(stream).await?;
+    http::write_header_end(stream).await
+}
+
+fn print_growth(name: &str, counts: &[usize], latencies: &[u128]) {
+    println!("{}: Latency growth", name);
+    for (count, latency) in counts.iter().zip(latencies) {
+        println!("{:>10} parameters: {:>8}ms", count, latency);
+    }
+}
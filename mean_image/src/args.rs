@@ -1,10 +1,190 @@
-use clap::Clap;
+use clap::{AppSettings, ArgEnum, Clap};
+use common::{bail, Context, Result};
+use std::{convert::TryFrom, path::PathBuf};
+
+const MAX_U24: u32 = 0x00ff_ffff;
 
 #[derive(Clap, Debug, Clone)]
 #[clap(author, about, version)]
 pub(crate) struct Args {
+    /// Format of the generated image, only subcommands work without one
+    #[clap(short, long, arg_enum, case_insensitive(true))]
+    pub(crate) format: Option<ImageFormat>,
+    /// Manipulation applied to the image
+    #[clap(
+        short,
+        long,
+        arg_enum,
+        case_insensitive(true),
+        default_value = "dimensions"
+    )]
+    pub(crate) mode: Mode,
+    /// Real width of the generated image, at least 1
+    #[clap(long, default_value = "512")]
+    pub(crate) width: u16,
+    /// Real height of the generated image, at least 1
+    #[clap(long, default_value = "512")]
+    pub(crate) height: u16,
+    /// Width claimed by the manipulated header
+    #[clap(long, default_value = "65500")]
+    pub(crate) new_width: u32,
+    /// Height claimed by the manipulated header
+    #[clap(long, default_value = "65500")]
+    pub(crate) new_height: u32,
+    /// Claims dimensions which overflow, overrides new-width and new-height
     #[clap(short, long, arg_enum, case_insensitive(true))]
-    pub(crate) format: ImageFormat,
+    pub(crate) preset: Option<Preset>,
+    /// File to write the image to [default: output.<format>], directory of the
+    /// corpus for fuzz [default: corpus]
+    #[clap(short, long, parse(from_os_str))]
+    pub(crate) output: Option<PathBuf>,
+    /// Seed for the noise in the image. A random one is used if not set
+    #[clap(short, long)]
+    pub(crate) seed: Option<u64>,
+    /// JPEG: Encodes the image progressively
+    #[clap(long)]
+    pub(crate) progressive: bool,
+    /// Fuzz: Number of mutated files written to the corpus
+    #[clap(long, default_value = "100")]
+    pub(crate) count: u32,
+    #[clap(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+impl Args {
+    pub(crate) fn format(&self) -> Result<&ImageFormat> {
+        self.format
+            .as_ref()
+            .context("--format is required to generate an image")
+    }
+
+    pub(crate) fn output_path(&self) -> Result<PathBuf> {
+        match &self.output {
+            Some(output) => Ok(output.clone()),
+            None => Ok(PathBuf::from(format!(
+                "output.{}",
+                self.format()?.extension()
+            ))),
+        }
+    }
+
+    pub(crate) fn corpus_path(&self) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("corpus"))
+    }
+
+    /// Claimed dimensions for formats which store them in 32 bit
+    pub(crate) fn new_dimensions_u32(&self) -> (u32, u32) {
+        self.preset
+            .map_or((self.new_width, self.new_height), Preset::dimensions_u32)
+    }
+
+    /// Claimed dimensions for formats which store them in 24 bit
+    pub(crate) fn new_dimensions_u24(&self) -> Result<(u32, u32)> {
+        match self.preset {
+            Some(preset) => Ok(preset.dimensions_u24()),
+            None if self.new_width > MAX_U24 || self.new_height > MAX_U24 => {
+                bail!("new-width and new-height must fit into 24 bit")
+            }
+            None => Ok((self.new_width, self.new_height)),
+        }
+    }
+
+    /// Claimed dimensions for formats which store them in 16 bit
+    pub(crate) fn new_dimensions_u16(&self) -> Result<(u16, u16)> {
+        match self.preset {
+            Some(preset) => preset.dimensions_u16(),
+            None => Ok((
+                u16::try_from(self.new_width).context("new-width must fit into 16 bit")?,
+                u16::try_from(self.new_height).context("new-height must fit into 16 bit")?,
+            )),
+        }
+    }
+}
+
+/// Name of the variant on the command line
+pub(crate) fn arg_name<T: ArgEnum + PartialEq>(value: &T) -> &'static str {
+    T::VARIANTS
+        .iter()
+        .find(|name| T::from_str(name, false).map_or(false, |variant| &variant == value))
+        .copied()
+        .unwrap_or_default()
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) enum Command {
+    /// Decodes the image in a child process with limited memory and time
+    Verify(Verify),
+    /// Posts the image to the server of the env file and checks whether it
+    /// survives
+    Upload(Upload),
+    /// Generates every format with every mode and writes a manifest
+    All(All),
+    /// Describes the structure of existing PNG, JPEG, GIF or BMP files and
+    /// lists their anomalies
+    Inspect(Inspect),
+    /// Decodes the image without any limits, used by verify
+    #[clap(setting = AppSettings::Hidden)]
+    Decode(Decode),
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) struct Verify {
+    /// Image to decode [default: the image generated using --format]
+    #[clap(parse(from_os_str))]
+    pub(crate) file: Option<PathBuf>,
+    /// Address space available to the decoder in MiB
+    #[clap(long, default_value = "1024")]
+    pub(crate) memory_limit: u64,
+    /// Seconds after which the decoder is killed
+    #[clap(long, default_value = "10")]
+    pub(crate) timeout: u64,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) struct Upload {
+    /// Image to upload [default: the image generated using --format]
+    #[clap(parse(from_os_str))]
+    pub(crate) file: Option<PathBuf>,
+    /// Url to post to [default: URL_UPLOAD, URL_BODY_TARGET or URL_RETURING_200]
+    #[clap(long)]
+    pub(crate) url: Option<String>,
+    /// Sends the image as multipart/form-data instead of the raw body
+    #[clap(long)]
+    pub(crate) multipart: bool,
+    /// Name of the multipart form field
+    #[clap(long, default_value = "file")]
+    pub(crate) field: String,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) struct All {
+    /// Directory to write the images and manifest.json to
+    #[clap(parse(from_os_str), default_value = "all")]
+    pub(crate) directory: PathBuf,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) struct Inspect {
+    /// Images to inspect
+    #[clap(parse(from_os_str), required = true)]
+    pub(crate) files: Vec<PathBuf>,
+    /// Decodes the images afterwards like verify does
+    #[clap(long)]
+    pub(crate) verify: bool,
+    /// Verify: Address space available to the decoder in MiB
+    #[clap(long, default_value = "1024")]
+    pub(crate) memory_limit: u64,
+    /// Verify: Seconds after which the decoder is killed
+    #[clap(long, default_value = "10")]
+    pub(crate) timeout: u64,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub(crate) struct Decode {
+    #[clap(parse(from_os_str))]
+    pub(crate) file: PathBuf,
 }
 
 #[derive(Clap, PartialEq, Eq, Debug, Clone)]
@@ -13,4 +193,146 @@ pub(crate) enum ImageFormat {
     JPEG,
     GIF,
     BMP,
+    TIFF,
+    WEBP,
+    ICO,
+    CUR,
+}
+
+impl ImageFormat {
+    pub(crate) fn extension(&self) -> &'static str {
+        match *self {
+            Self::PNG => "png",
+            Self::JPEG => "jpeg",
+            Self::GIF => "gif",
+            Self::BMP => "bmp",
+            Self::TIFF => "tiff",
+            Self::WEBP => "webp",
+            Self::ICO => "ico",
+            Self::CUR => "cur",
+        }
+    }
+}
+
+#[derive(Clap, PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum Mode {
+    /// Claims new-width x new-height or the preset dimensions
+    Dimensions,
+    /// PNG: IDAT really decompresses to new-width x new-height black pixels
+    DecompressionBomb,
+    /// PNG: zTXt chunk which decompresses to 1 GiB
+    ZtxtBomb,
+    /// PNG: iCCP chunk which decompresses to 1 GiB
+    IccpBomb,
+    /// PNG: 10000 tEXt chunks
+    TextFlood,
+    /// PNG: tEXt chunk whose length exceeds the file
+    ChunkLength,
+    /// PNG: Unknown critical chunk which decoders have to reject
+    CriticalChunk,
+    /// PNG: APNG whose acTL claims 10 million frames and plays
+    FrameCount,
+    /// PNG: APNG frame of new-width x new-height placed outside the canvas
+    FrameBounds,
+    /// GIF: 10000 full screen frames with tiny LZW streams
+    FrameBomb,
+    /// GIF: LZW minimum code size of 12, so codes exceed 12 bit
+    LzwCodeSize,
+    /// GIF: Frames without delay looping forever
+    InfiniteAnimation,
+    /// JPEG: 1135 progressive scans of single, repeated coefficients
+    ScanFlood,
+    /// JPEG: DHT whose code lengths claim 4080 codes
+    HuffmanTable,
+    /// JPEG: DRI restart interval of 65535 MCUs without restart markers
+    RestartInterval,
+    /// JPEG: SOF claims 255 components while the scans contain 3
+    ComponentCount,
+    /// JPEG/PNG: EXIF whose IFD0 links to itself as next and Exif IFD
+    ExifCyclicIfd,
+    /// JPEG/PNG: EXIF tags claim 2^30 values
+    ExifTagCount,
+    /// JPEG: Extended XMP claims 4 GiB, PNG: iTXt XMP decompresses to 1 GiB
+    OversizedXmp,
+    /// JPEG/PNG: EXIF thumbnail claims 65535x65535 pixels
+    ThumbnailDimensions,
+    /// BMP: Height of -2^31, whose absolute value overflows
+    NegativeHeight,
+    /// BMP: Valid top-down image with a height of -height
+    TopDown,
+    /// BMP: V5 header whose embedded ICC profile starts at the end of the file
+    ProfileOffset,
+    /// BMP: RLE8 runs which decode to new-width x new-height pixels
+    Rle8Bomb,
+    /// BMP: RLE4 runs which decode to new-width x new-height pixels
+    Rle4Bomb,
+    /// BMP: biClrUsed claims a palette of 2^30 colors
+    PaletteSize,
+    /// GIF/PNG/JPEG/BMP: Also parses as JavaScript, ZIP, HTML or PDF
+    Polyglot,
+    /// PNG/JPEG/GIF/BMP: Corpus of files with random structure-aware mutations
+    Fuzz,
+    /// TIFF: StripByteCounts claim more data than the file contains
+    StripByteCounts,
+    /// TIFF: The next IFD offset points back to the first IFD
+    CyclicIfd,
+    /// TIFF: Strip tags claim a huge amount of values
+    TagCount,
+    /// WEBP: RIFF and chunk sizes claim more data than the file contains
+    RiffSize,
+    /// ICO/CUR: Directory entries claim 1x1 pixels and more data than the file contains
+    EntrySize,
+    /// ICO/CUR: All directory entries point to the same overlapping data
+    OverlappingOffsets,
+    /// ICO/CUR: The directory contains 65535 entries
+    EntryCount,
+}
+
+#[derive(Clap, PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum Preset {
+    /// Largest dimensions the format can store
+    Max,
+    /// `width * height` wraps to 0 in 32 bit math
+    WrapPixels,
+    /// `width * height * 3` wraps to a small value in 32 bit math
+    WrapRgb,
+    /// `width * height * 4` wraps to a small value in 32 bit math
+    WrapRgba,
+}
+
+impl Preset {
+    fn dimensions_u32(self) -> (u32, u32) {
+        match self {
+            Self::Max => (u32::max_value(), u32::max_value()),
+            // 2^16 * 2^16 = 2^32
+            Self::WrapPixels => (0x0001_0000, 0x0001_0000),
+            // 0x5555_5556 * 3 = 2^32 + 2
+            Self::WrapRgb => (0x5555_5556, 1),
+            // 0x4000_0001 * 4 = 2^32 + 4
+            Self::WrapRgba => (0x4000_0001, 1),
+        }
+    }
+
+    fn dimensions_u24(self) -> (u32, u32) {
+        match self {
+            Self::Max => (MAX_U24, MAX_U24),
+            // 2^16 * 2^16 = 2^32
+            Self::WrapPixels => (0x0001_0000, 0x0001_0000),
+            // 0x00aa_aaab * 3 * 0x80 = 2^32 + 2^7
+            Self::WrapRgb => (0x00aa_aaab, 0x80),
+            // 0x0080_0001 * 4 * 0x80 = 2^32 + 2^9
+            Self::WrapRgba => (0x0080_0001, 0x80),
+        }
+    }
+
+    fn dimensions_u16(self) -> Result<(u16, u16)> {
+        match self {
+            Self::Max => Ok((u16::max_value(), u16::max_value())),
+            Self::WrapPixels => bail!("16 bit dimensions cannot wrap width * height in 32 bit"),
+            // 0xaaab * 3 * 0x8000 = 2^32 + 2^15
+            Self::WrapRgb => Ok((0xaaab, 0x8000)),
+            // 0x8000 * 0x8000 * 4 = 2^32
+            Self::WrapRgba => Ok((0x8000, 0x8000)),
+        }
+    }
 }
@@ -0,0 +1,294 @@
+//! Generates every format with every mode into a directory. A JSON manifest
+//! describes each file, so regression suites know what to expect from it.
+
+use crate::{
+    args::{arg_name, Args, ImageFormat, Mode, Preset},
+    bmp, ico, metadata, modes,
+};
+use common::{Context, Result};
+use image::{ImageBuffer, Rgb};
+use std::{fmt::Write, fs, path::Path};
+
+const FORMATS: &[ImageFormat] = &[
+    ImageFormat::PNG,
+    ImageFormat::JPEG,
+    ImageFormat::GIF,
+    ImageFormat::BMP,
+    ImageFormat::TIFF,
+    ImageFormat::WEBP,
+    ImageFormat::ICO,
+    ImageFormat::CUR,
+];
+const PRESETS: &[Preset] = &[
+    Preset::Max,
+    Preset::WrapPixels,
+    Preset::WrapRgb,
+    Preset::WrapRgba,
+];
+const MANIFEST: &str = "manifest.json";
+
+/// How a robust decoder treats a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expectation {
+    /// The file is invalid or claims more than it contains and has to be
+    /// rejected
+    Reject,
+    /// The file is valid, but exhausts memory or time unless the decoder
+    /// limits them
+    Limit,
+    /// The pixels are intact and decode like any other image, only metadata
+    /// or other parsers are attacked
+    Decode,
+    /// Anything may happen, like for fuzzed files
+    Any,
+}
+
+impl Expectation {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Limit => "limit",
+            Self::Decode => "decode",
+            Self::Any => "any",
+        }
+    }
+}
+
+/// Description of a generated file or corpus
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Path relative to the manifest
+    file: String,
+    /// Number of files, only fuzzing writes more than one
+    count: u32,
+    format: &'static str,
+    mode: &'static str,
+    preset: Option<&'static str>,
+    progressive: bool,
+    width: u16,
+    height: u16,
+    /// Dimensions claimed by the manipulated file, if they differ
+    claimed: Option<(i64, i64)>,
+    expected: Expectation,
+}
+
+/// Writes every variant into `<directory>/<format>/` and skips presets which
+/// do not fit into the format
+pub(crate) fn create_all(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    args: &Args,
+    directory: &Path,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    for format in FORMATS {
+        let extension = format.extension();
+        fs::create_dir_all(directory.join(extension))
+            .context("Unable to create output directory")?;
+        for &mode in modes(format) {
+            let presets = if mode == Mode::Dimensions {
+                let mut presets = vec![None];
+                presets.extend(PRESETS.iter().copied().map(Some));
+                presets
+            } else {
+                vec![args.preset]
+            };
+            for preset in presets {
+                let mut file = format!("{}/{}", extension, arg_name(&mode));
+                if let Some(preset) = preset {
+                    write!(file, "-{}", arg_name(&preset)).context("Unable to format name")?;
+                }
+                if mode != Mode::Fuzz {
+                    write!(file, ".{}", extension).context("Unable to format name")?;
+                }
+                let variant = Args {
+                    format: Some(format.clone()),
+                    mode,
+                    preset,
+                    output: Some(directory.join(&file)),
+                    command: None,
+                    ..args.clone()
+                };
+                if let Err(error) = fit_preset(&variant) {
+                    println!("Skipping {}: {:#}", file, error);
+                    continue;
+                }
+                println!("Generating {}", file);
+                crate::create_image(image, &variant)
+                    .with_context(|| format!("Unable to generate {}", file))?;
+                let claimed = claimed_dimensions(&variant)?;
+                entries.push(Entry {
+                    file,
+                    count: if mode == Mode::Fuzz { args.count } else { 1 },
+                    format: extension,
+                    mode: arg_name(&mode),
+                    preset: preset.map(|preset| arg_name(&preset)),
+                    progressive: *format == ImageFormat::JPEG && args.progressive,
+                    width: args.width,
+                    height: args.height,
+                    claimed,
+                    expected: expectation(mode),
+                });
+            }
+        }
+    }
+    let manifest = directory.join(MANIFEST);
+    fs::write(&manifest, manifest_json(&entries)?).context("Unable to write manifest")?;
+    println!(
+        "Wrote {} variants and {}",
+        entries.len(),
+        manifest.display()
+    );
+    Ok(())
+}
+
+/// Fails if the format cannot store the dimensions of the preset
+fn fit_preset(args: &Args) -> Result<()> {
+    if args.preset.is_some() {
+        match args.format()? {
+            ImageFormat::JPEG | ImageFormat::GIF => {
+                let _ = args.new_dimensions_u16()?;
+            }
+            ImageFormat::WEBP => {
+                let _ = args.new_dimensions_u24()?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn expectation(mode: Mode) -> Expectation {
+    match mode {
+        Mode::DecompressionBomb
+        | Mode::ZtxtBomb
+        | Mode::IccpBomb
+        | Mode::TextFlood
+        | Mode::FrameCount
+        | Mode::FrameBomb
+        | Mode::InfiniteAnimation
+        | Mode::ScanFlood
+        | Mode::Rle8Bomb
+        | Mode::Rle4Bomb
+        | Mode::EntryCount => Expectation::Limit,
+        Mode::RestartInterval
+        | Mode::ExifCyclicIfd
+        | Mode::ExifTagCount
+        | Mode::OversizedXmp
+        | Mode::ThumbnailDimensions
+        | Mode::ProfileOffset
+        | Mode::TopDown
+        | Mode::Polyglot => Expectation::Decode,
+        Mode::Fuzz => Expectation::Any,
+        _ => Expectation::Reject,
+    }
+}
+
+/// Dimensions the mode claims instead of the real ones
+fn claimed_dimensions(args: &Args) -> Result<Option<(i64, i64)>> {
+    let format = args.format()?;
+    let claimed = match args.mode {
+        Mode::Dimensions => match format {
+            ImageFormat::JPEG | ImageFormat::GIF => {
+                let (width, height) = args.new_dimensions_u16()?;
+                (width.into(), height.into())
+            }
+            ImageFormat::WEBP => {
+                let (width, height) = args.new_dimensions_u24()?;
+                (width.into(), height.into())
+            }
+            ImageFormat::BMP => {
+                let (width, height) = args.new_dimensions_u32();
+                let (width, height) = bmp::signed_dimensions(width, height);
+                (width.into(), height.into())
+            }
+            // the embedded PNG claims the same, as long as the width and the
+            // doubled height fit into the signed DIB
+            ImageFormat::ICO | ImageFormat::CUR => {
+                let (width, height) = args.new_dimensions_u32();
+                let (width, height) = ico::bmp_dimensions(width, height);
+                (width.into(), height.into())
+            }
+            _ => {
+                let (width, height) = args.new_dimensions_u32();
+                (width.into(), height.into())
+            }
+        },
+        Mode::DecompressionBomb | Mode::FrameBounds => {
+            let (width, height) = args.new_dimensions_u32();
+            (width.into(), height.into())
+        }
+        Mode::Rle8Bomb | Mode::Rle4Bomb => {
+            let (width, height) = args.new_dimensions_u32();
+            let (width, height) = bmp::signed_dimensions(width, height);
+            (width.into(), height.into())
+        }
+        // the absolute value of the height does not fit into 32 bit
+        Mode::NegativeHeight => {
+            let (width, height) = bmp::signed_dimensions(args.width.into(), bmp::NEGATIVE_HEIGHT);
+            (width.into(), height.into())
+        }
+        Mode::TopDown => (args.width.into(), -i64::from(args.height)),
+        // every directory entry claims a single pixel
+        Mode::EntrySize => (1, 1),
+        Mode::ThumbnailDimensions => {
+            let dimension = metadata::HUGE_THUMBNAIL_DIMENSION.into();
+            (dimension, dimension)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(claimed))
+}
+
+fn manifest_json(entries: &[Entry]) -> Result<String> {
+    let mut json = String::from("[\n");
+    for (index, entry) in entries.iter().enumerate() {
+        let preset = entry.preset.map_or_else(|| "null".into(), json_string);
+        let (claimed_width, claimed_height) = entry.claimed.map_or_else(
+            || ("null".into(), "null".into()),
+            |(width, height)| (width.to_string(), height.to_string()),
+        );
+        write!(
+            json,
+            concat!(
+                "  {{\"file\": {}, \"count\": {}, \"format\": {}, \"mode\": {}, ",
+                "\"preset\": {}, \"progressive\": {}, \"width\": {}, \"height\": {}, ",
+                "\"claimed_width\": {}, \"claimed_height\": {}, \"expected\": {}}}"
+            ),
+            json_string(&entry.file),
+            entry.count,
+            json_string(entry.format),
+            json_string(entry.mode),
+            preset,
+            entry.progressive,
+            entry.width,
+            entry.height,
+            claimed_width,
+            claimed_height,
+            json_string(entry.expected.name()),
+        )
+        .context("Unable to format manifest")?;
+        json.push_str(if index + 1 < entries.len() {
+            ",\n"
+        } else {
+            "\n"
+        });
+    }
+    json.push_str("]\n");
+    Ok(json)
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            character if character.is_control() => {
+                output.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => output.push(character),
+        }
+    }
+    output.push('"');
+    output
+}
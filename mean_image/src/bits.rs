@@ -0,0 +1,69 @@
+/// Writes values least significant bit first as required by VP8L and
+/// Deflate
+#[derive(Debug, Default)]
+pub(crate) struct BitWriter {
+    data: Vec<u8>,
+    used: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn write(&mut self, value: u32, bits: u32) {
+        for bit in 0..bits {
+            if self.used == 0 {
+                self.data.push(0);
+            }
+            let last = self.data.len() - 1;
+            self.data[last] |= (((value >> bit) & 1) as u8) << self.used;
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Writes a prefix code, which starts with its most significant bit
+    pub(crate) fn write_code(&mut self, code: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.write(code >> bit, 1);
+        }
+    }
+
+    /// Writes many zero bits at once
+    pub(crate) fn write_zeros(&mut self, bits: u64) {
+        let unaligned = ((8 - self.used as u64) % 8).min(bits);
+        self.write(0, unaligned as u32);
+        let bytes = (bits - unaligned) / 8;
+        self.data.resize(self.data.len() + bytes as usize, 0);
+        self.write(0, ((bits - unaligned) % 8) as u32);
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitWriter;
+
+    #[test]
+    fn values_start_with_least_significant_bit() {
+        let mut writer = BitWriter::default();
+        writer.write(0b101, 3);
+        writer.write(0b11_0000, 6);
+        assert_eq!(vec![0b1000_0101, 0b1], writer.into_bytes());
+    }
+
+    #[test]
+    fn codes_start_with_most_significant_bit() {
+        let mut writer = BitWriter::default();
+        writer.write_code(0b110, 3);
+        assert_eq!(vec![0b011], writer.into_bytes());
+    }
+
+    #[test]
+    fn zeros_keep_alignment() {
+        let mut writer = BitWriter::default();
+        writer.write(1, 1);
+        writer.write_zeros(20);
+        writer.write(1, 1);
+        assert_eq!(vec![1, 0, 0b10_0000], writer.into_bytes());
+    }
+}
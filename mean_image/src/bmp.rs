@@ -1,18 +1,160 @@
-use crate::{extract_u32_le, write_u32_le, HEIGHT, NEW_HEIGHT, NEW_WIDTH, WIDTH};
-use common::{Context, Result};
-use image::{bmp::BMPEncoder, EncodableLayout, ImageBuffer, Pixel, Rgb};
-use std::fs;
-
-pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<()> {
-    let mut image = create_png(image)?;
-    verify_image(&image, WIDTH, HEIGHT);
-    modify_width_and_height(&mut image, NEW_WIDTH, NEW_HEIGHT);
-    verify_image(&image, NEW_WIDTH, NEW_HEIGHT);
-    fs::write("output.bmp", image).context("Unable to write to image file")?;
+use crate::{
+    args::{Args, Mode},
+    extract_u16_le, extract_u32_le,
+    fuzz::{self, Field, Structure},
+    inspect::Report,
+    polyglot::{self, Format},
+    write_u16_le, write_u32_le,
+};
+use common::{bail, Context, Result};
+use image::{bmp::BMPEncoder, imageops, EncodableLayout, ImageBuffer, Pixel, Rgb};
+use std::{convert::TryFrom, fs};
+
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::NegativeHeight,
+    Mode::TopDown,
+    Mode::ProfileOffset,
+    Mode::Rle8Bomb,
+    Mode::Rle4Bomb,
+    Mode::PaletteSize,
+    Mode::Polyglot,
+    Mode::Fuzz,
+];
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: u32 = 12;
+const INFO_HEADER_SIZE: u32 = 40;
+const V5_HEADER_SIZE: u32 = 124;
+const HEADERS: &[(u32, &str)] = &[
+    (CORE_HEADER_SIZE, "BITMAPCOREHEADER"),
+    (INFO_HEADER_SIZE, "BITMAPINFOHEADER"),
+    (52, "BITMAPV2INFOHEADER"),
+    (56, "BITMAPV3INFOHEADER"),
+    (108, "BITMAPV4HEADER"),
+    (V5_HEADER_SIZE, "BITMAPV5HEADER"),
+];
+const BIT_COUNTS: &[u16] = &[1, 2, 4, 8, 16, 24, 32];
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+// Red, green and blue masks following a BITMAPINFOHEADER
+const BITFIELDS_SIZE: usize = 12;
+// 'MBED'
+const PROFILE_EMBEDDED: u32 = 0x4d42_4544;
+const LCS_GM_IMAGES: u32 = 4;
+
+const MAX_RUN: u32 = 255;
+// Keeps the RLE data at about 64 MiB
+const MAX_RLE_PIXELS: u64 = 1 << 33;
+// Absolute value does not fit into 32 bit signed integers
+pub(crate) const NEGATIVE_HEIGHT: u32 = 0x8000_0000;
+// 4 GiB of palette entries
+const HUGE_COLOR_COUNT: u32 = 0x4000_0000;
+
+// Tags of the fields
+const FILE_HEADER: u32 = 0;
+const INFO_HEADER: u32 = 1;
+const PALETTE: u32 = 2;
+const PIXELS: u32 = 3;
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let (width, height) = (args.width.into(), args.height.into());
+    let (new_width, new_height) = args.new_dimensions_u32();
+    let image = match args.mode {
+        Mode::Dimensions => {
+            let mut image = create_bmp(image)?;
+            verify_image(&image, width, height);
+            modify_width_and_height(&mut image, new_width, new_height);
+            verify_image(&image, new_width, new_height);
+            image
+        }
+        Mode::NegativeHeight => {
+            let mut image = create_bmp(image)?;
+            verify_image(&image, width, height);
+            write_u32_le(&mut image, 22, NEGATIVE_HEIGHT);
+            verify_image(&image, width, NEGATIVE_HEIGHT);
+            image
+        }
+        Mode::TopDown => {
+            let mut bmp = create_bmp(image)?;
+            verify_image(&bmp, width, height);
+            flip_rows(&mut bmp, width, height)?;
+            // two's complement of -height
+            let top_down_height = 0_u32.wrapping_sub(height);
+            write_u32_le(&mut bmp, 22, top_down_height);
+            verify_image(&bmp, width, top_down_height);
+            verify_top_row(&bmp, image);
+            bmp
+        }
+        Mode::ProfileOffset => {
+            let image = create_bmp(image)?;
+            verify_image(&image, width, height);
+            let image = create_v5(&image)?;
+            verify_image(&image, width, height);
+            verify_profile(&image);
+            image
+        }
+        Mode::Rle8Bomb | Mode::Rle4Bomb => {
+            let (bit_count, compression) = if args.mode == Mode::Rle8Bomb {
+                (8, BI_RLE8)
+            } else {
+                (4, BI_RLE4)
+            };
+            let data = rle_zeros(new_width, new_height)?;
+            // black and white
+            let palette = [[0; 3], [0xff; 3]];
+            let image = create_indexed(
+                (new_width, new_height),
+                bit_count,
+                compression,
+                &palette,
+                &data,
+            );
+            verify_image(&image, new_width, new_height);
+            let pixels = verify_rle(&image, compression)?;
+            println!(
+                "RLE data of {} bytes decompresses to {} pixels",
+                data.len(),
+                pixels
+            );
+            image
+        }
+        Mode::PaletteSize => {
+            let mut image = create_grayscale(image)?;
+            verify_image(&image, width, height);
+            write_u32_le(&mut image, 46, HUGE_COLOR_COUNT);
+            verify_image(&image, width, height);
+            assert_eq!(
+                HUGE_COLOR_COUNT,
+                extract_u32_le(&image, 46),
+                "Color count is invalid"
+            );
+            image
+        }
+        Mode::Polyglot => {
+            let image = create_bmp(image)?;
+            verify_image(&image, width, height);
+            let image = insert_pdf(&image);
+            verify_image(&image, width, height);
+            polyglot::verify_formats(&image, &[Format::Bmp, Format::Pdf])?;
+            image
+        }
+        Mode::Fuzz => {
+            // 8 bit, so there is a palette to mutate
+            let image = create_grayscale(image)?;
+            verify_image(&image, width, height);
+            return fuzz::write_corpus(&image, &structure(&image), args);
+        }
+        _ => bail!("Mode is not supported by BMP"),
+    };
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
     Ok(())
 }
 
-fn create_png(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+pub(crate) fn create_bmp(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     let mut output: Vec<u8> = Vec::new();
     let mut encoder = BMPEncoder::new(&mut output);
     encoder
@@ -26,16 +168,431 @@ fn create_png(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-fn verify_image(image: &[u8], width: u16, height: u16) {
+fn verify_image(image: &[u8], width: u32, height: u32) {
     assert_eq!(b"BM", &image[0..2], "BMP Signature is not valid");
     let img_width = extract_u32_le(image, 18);
     let img_height = extract_u32_le(image, 22);
-    assert_eq!(width as u32, img_width, "Image width is invalid");
-    assert_eq!(height as u32, img_height, "Image height is invalid");
+    assert_eq!(width, img_width, "Image width is invalid");
+    assert_eq!(height, img_height, "Image height is invalid");
 }
 
-fn modify_width_and_height(image: &mut [u8], new_width: u16, new_height: u16) {
+fn modify_width_and_height(image: &mut [u8], new_width: u32, new_height: u32) {
     write_u32_le(image, 2, u32::max_value());
-    write_u32_le(image, 18, new_width as u32);
-    write_u32_le(image, 22, new_height as u32);
+    write_u32_le(image, 18, new_width);
+    write_u32_le(image, 22, new_height);
+}
+
+/// Reverses the rows of a 24 bit image, so the top row comes first
+fn flip_rows(image: &mut [u8], width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        bail!("BMP rows cannot be flipped without pixels");
+    }
+    let offset = extract_u32_le(image, 10) as usize;
+    // rows are padded to 4 bytes
+    let row_size = (width as usize * 3 + 3) & !3;
+    let pixels = image[offset..offset + row_size * height as usize].to_vec();
+    for (index, row) in pixels.chunks(row_size).rev().enumerate() {
+        let pos = offset + index * row_size;
+        image[pos..pos + row_size].copy_from_slice(row);
+    }
+    Ok(())
+}
+
+fn verify_top_row(image: &[u8], source: &ImageBuffer<Rgb<u8>, Vec<u8>>) {
+    let offset = extract_u32_le(image, 10) as usize;
+    let [red, green, blue] = source.get_pixel(0, 0).0;
+    assert_eq!(
+        &[blue, green, red],
+        &image[offset..offset + 3],
+        "Top row is not stored first"
+    );
+}
+
+/// Indexed bottom-up BMP with a BITMAPINFOHEADER
+fn create_indexed(
+    (width, height): (u32, u32),
+    bit_count: u16,
+    compression: u32,
+    palette: &[[u8; 3]],
+    data: &[u8],
+) -> Vec<u8> {
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE as usize + palette.len() * 4;
+    let mut output = vec![0; offset];
+    output[0..2].copy_from_slice(b"BM");
+    write_u32_le(&mut output, 2, (offset + data.len()) as u32);
+    write_u32_le(&mut output, 10, offset as u32);
+    write_u32_le(&mut output, 14, INFO_HEADER_SIZE);
+    write_u32_le(&mut output, 18, width);
+    write_u32_le(&mut output, 22, height);
+    write_u16_le(&mut output, 26, 1);
+    write_u16_le(&mut output, 28, bit_count);
+    write_u32_le(&mut output, 30, compression);
+    write_u32_le(&mut output, 34, data.len() as u32);
+    write_u32_le(&mut output, 46, palette.len() as u32);
+    for (index, [red, green, blue]) in palette.iter().enumerate() {
+        let pos = FILE_HEADER_SIZE + INFO_HEADER_SIZE as usize + index * 4;
+        output[pos..pos + 3].copy_from_slice(&[*blue, *green, *red]);
+    }
+    output.extend_from_slice(data);
+    output
+}
+
+/// 8 bit BMP using a palette of 256 shades of gray
+fn create_grayscale(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let image = imageops::grayscale(image);
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        bail!("Grayscale BMP needs at least a single pixel");
+    }
+    // rows are padded to 4 bytes
+    let row_size = (width as usize + 3) / 4 * 4;
+    let mut data = vec![0; row_size * height as usize];
+    for (row, pixels) in data.chunks_mut(row_size).enumerate() {
+        let y = height - 1 - row as u32;
+        for x in 0..width {
+            pixels[x as usize] = image.get_pixel(x, y).0[0];
+        }
+    }
+    let palette: Vec<[u8; 3]> = (0..=255).map(|value| [value; 3]).collect();
+    Ok(create_indexed((width, height), 8, BI_RGB, &palette, &data))
+}
+
+/// Turns the BITMAPINFOHEADER into a BITMAPV5HEADER whose embedded ICC
+/// profile starts at the end of the file and claims 4 GiB
+fn create_v5(image: &[u8]) -> Result<Vec<u8>> {
+    let header_size = extract_u32_le(image, 14);
+    if header_size != INFO_HEADER_SIZE {
+        bail!("BMP header has an unexpected size of {}", header_size);
+    }
+    let extension = (V5_HEADER_SIZE - INFO_HEADER_SIZE) as usize;
+    let end = FILE_HEADER_SIZE + INFO_HEADER_SIZE as usize;
+    let mut output = image[..end].to_vec();
+    output.resize(end + extension, 0);
+    output.extend_from_slice(&image[end..]);
+
+    let offset = extract_u32_le(&output, 10) + extension as u32;
+    let size = output.len() as u32;
+    write_u32_le(&mut output, 2, size);
+    write_u32_le(&mut output, 10, offset);
+    write_u32_le(&mut output, 14, V5_HEADER_SIZE);
+    write_u32_le(&mut output, 70, PROFILE_EMBEDDED);
+    write_u32_le(&mut output, 122, LCS_GM_IMAGES);
+    // the profile offset is relative to the start of the header
+    let profile = (output.len() - FILE_HEADER_SIZE) as u32;
+    write_u32_le(&mut output, 126, profile);
+    write_u32_le(&mut output, 130, u32::max_value());
+    Ok(output)
+}
+
+fn verify_profile(image: &[u8]) {
+    assert_eq!(
+        V5_HEADER_SIZE,
+        extract_u32_le(image, 14),
+        "Header size is invalid"
+    );
+    assert_eq!(
+        PROFILE_EMBEDDED,
+        extract_u32_le(image, 70),
+        "Color space type is invalid"
+    );
+    let profile = extract_u32_le(image, 126) as usize;
+    assert_eq!(
+        image.len(),
+        FILE_HEADER_SIZE + profile,
+        "Profile offset is invalid"
+    );
+    assert_eq!(
+        u32::max_value(),
+        extract_u32_le(image, 130),
+        "Profile size is invalid"
+    );
+}
+
+/// Encodes every row as runs of 255 pixels with the color 0, the same
+/// stream works for RLE8 and RLE4
+fn rle_zeros(width: u32, height: u32) -> Result<Vec<u8>> {
+    if i32::try_from(width).is_err() || i32::try_from(height).is_err() {
+        bail!("BMP dimensions have to fit into 31 bit");
+    }
+    if width as u64 * height as u64 > MAX_RLE_PIXELS {
+        bail!("RLE bomb would take too much space");
+    }
+    let mut row = vec![MAX_RUN as u8, 0].repeat((width / MAX_RUN) as usize);
+    if width % MAX_RUN != 0 {
+        row.extend_from_slice(&[(width % MAX_RUN) as u8, 0]);
+    }
+    // end of line
+    row.extend_from_slice(&[0, 0]);
+    let mut data = row.repeat(height as usize);
+    // end of bitmap
+    data.extend_from_slice(&[0, 1]);
+    Ok(data)
+}
+
+/// Verifies the compression and returns the number of encoded pixels
+fn verify_rle(image: &[u8], compression: u32) -> Result<u64> {
+    assert_eq!(
+        compression,
+        extract_u32_le(image, 30),
+        "Compression is invalid"
+    );
+    let bit_count = if compression == BI_RLE8 { 8 } else { 4 };
+    assert_eq!(bit_count, extract_u16_le(image, 28), "Bit count is invalid");
+    let pixels = rle_pixels(image, compression)?;
+    let width = extract_u32_le(image, 18) as u64;
+    let height = extract_u32_le(image, 22) as u64;
+    assert_eq!(width * height, pixels, "RLE pixel count is invalid");
+    Ok(pixels)
+}
+
+/// Number of pixels the RLE data encodes including those skipped by deltas
+fn rle_pixels(image: &[u8], compression: u32) -> Result<u64> {
+    let width = extract_u32_le(image, 18) as u64;
+    let mut pixels = 0;
+    let mut pos = extract_u32_le(image, 10) as usize;
+    loop {
+        let (count, value) = match image.get(pos..pos + 2) {
+            Some(&[count, value]) => (count, value),
+            _ => bail!("RLE data ends without end of bitmap"),
+        };
+        pos += 2;
+        match (count, value) {
+            (0, 0) => {}
+            (0, 1) => return Ok(pixels),
+            (0, 2) => {
+                let (dx, dy) = match image.get(pos..pos + 2) {
+                    Some(&[dx, dy]) => (dx as u64, dy as u64),
+                    _ => bail!("RLE delta exceeds the file"),
+                };
+                pixels += dx + dy * width;
+                pos += 2;
+            }
+            (0, absolute) => {
+                let size = if compression == BI_RLE8 {
+                    absolute as usize
+                } else {
+                    (absolute as usize + 1) / 2
+                };
+                pixels += absolute as u64;
+                // absolute runs are padded to 2 bytes
+                pos += size + size % 2;
+            }
+            (count, _) => pixels += count as u64,
+        }
+    }
+}
+
+/// Both headers, the palette and the pixels are fields. The file size and
+/// the offset of the pixels are fixed up.
+fn structure(image: &[u8]) -> Structure {
+    let header_end = FILE_HEADER_SIZE + extract_u32_le(image, 14) as usize;
+    let offset = extract_u32_le(image, 10) as usize;
+    let mut fields = vec![
+        Field {
+            tag: FILE_HEADER,
+            data: image[..FILE_HEADER_SIZE].to_vec(),
+        },
+        Field {
+            tag: INFO_HEADER,
+            data: image[FILE_HEADER_SIZE..header_end].to_vec(),
+        },
+    ];
+    if offset > header_end {
+        fields.push(Field {
+            tag: PALETTE,
+            data: image[header_end..offset].to_vec(),
+        });
+    }
+    fields.push(Field {
+        tag: PIXELS,
+        data: image[offset..].to_vec(),
+    });
+    Structure {
+        fields,
+        fixed: 2,
+        big_endian: false,
+        assemble: assemble_fields,
+    }
+}
+
+fn assemble_fields(fields: &[Field]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut offset = None;
+    for field in fields {
+        if field.tag == PIXELS && offset.is_none() {
+            offset = Some(output.len());
+        }
+        output.extend_from_slice(&field.data);
+    }
+    // a mutation may have truncated the file header, which always comes first
+    if fields[0].data.len() >= FILE_HEADER_SIZE {
+        let size = output.len();
+        write_u32_le(&mut output, 2, size as u32);
+        write_u32_le(&mut output, 10, offset.unwrap_or(size) as u32);
+    }
+    Ok(output)
+}
+
+/// Places a PDF between the headers and the pixels, only the end of the PDF
+/// follows the pixels
+fn insert_pdf(image: &[u8]) -> Vec<u8> {
+    let offset = extract_u32_le(image, 10) as usize;
+    let (body, end) = polyglot::pdf(offset);
+    let mut output = image[..offset].to_vec();
+    output.extend_from_slice(&body);
+    output.extend_from_slice(&image[offset..]);
+    output.extend(end);
+    let size = output.len() as u32;
+    write_u32_le(&mut output, 2, size);
+    write_u32_le(&mut output, 10, (offset + body.len()) as u32);
+    output
+}
+
+/// Width and height as decoders read them from the header, both are signed
+pub(crate) fn signed_dimensions(width: u32, height: u32) -> (i32, i32) {
+    (width as i32, height as i32)
+}
+
+/// Describes both headers, the palette and the pixels
+pub(crate) fn inspect(image: &[u8], report: &mut Report) {
+    if image.len() < FILE_HEADER_SIZE + 4 {
+        report.anomaly("File header exceeds the file");
+        return;
+    }
+    let size = extract_u32_le(image, 2);
+    let offset = extract_u32_le(image, 10) as usize;
+    let header_size = extract_u32_le(image, 14);
+    report.field("File size", size);
+    report.field("Pixel offset", offset);
+    report.field("Header size", header_size);
+    if size as usize != image.len() {
+        report.anomaly(format!(
+            "File size of {} does not match the {} bytes of the file",
+            size,
+            image.len()
+        ));
+    }
+    let header_end = FILE_HEADER_SIZE + header_size as usize;
+    let header = HEADERS.iter().find(|(known, _)| *known == header_size);
+    let header = match header {
+        Some(_) if header_end > image.len() => {
+            report.anomaly("Header exceeds the file");
+            return;
+        }
+        Some((_, header)) => header,
+        None => {
+            report.anomaly(format!("Header size of {} is unknown", header_size));
+            return;
+        }
+    };
+    report.part("File header", 0, FILE_HEADER_SIZE);
+    report.part(*header, FILE_HEADER_SIZE, header_size as usize);
+
+    let (width, height, bit_count, compression, colors) = if header_size == CORE_HEADER_SIZE {
+        let width = extract_u16_le(image, 18) as i32;
+        let height = extract_u16_le(image, 20) as i32;
+        (width, height, extract_u16_le(image, 24), BI_RGB, 0)
+    } else {
+        let (width, height) =
+            signed_dimensions(extract_u32_le(image, 18), extract_u32_le(image, 22));
+        let colors = extract_u32_le(image, 46);
+        (
+            width,
+            height,
+            extract_u16_le(image, 28),
+            extract_u32_le(image, 30),
+            colors,
+        )
+    };
+    report.field("Width", width);
+    report.field("Height", height);
+    report.field("Bit count", bit_count);
+    report.field("Compression", compression);
+    report.field("Colors used", colors);
+    // decoders reading the width unsigned see the huge value instead
+    report.claimed = Some((width as u32 as u64, (height as i64).abs() as u64));
+    if width < 0 {
+        report.anomaly(format!("Width of {} is negative", width));
+    }
+    // top-down images have a negative height
+    if height == i32::min_value() {
+        report.anomaly(format!("Absolute value of the height {} overflows", height));
+    }
+    if !BIT_COUNTS.contains(&bit_count) {
+        report.anomaly(format!("Bit count of {} is invalid", bit_count));
+    }
+    match compression {
+        BI_RLE8 if bit_count != 8 => report.anomaly("RLE8 requires a bit count of 8"),
+        BI_RLE4 if bit_count != 4 => report.anomaly("RLE4 requires a bit count of 4"),
+        _ => {}
+    }
+
+    // only indexed images have a palette, it has an entry for every color
+    // unless the header uses fewer
+    let mut palette_size = 0;
+    if (1..=8).contains(&bit_count) {
+        let max_colors = 1 << bit_count;
+        if colors > max_colors {
+            report.anomaly(format!(
+                "{} colors exceed the {} of a bit count of {}",
+                colors, max_colors, bit_count
+            ));
+        }
+        let entries = if colors == 0 { max_colors } else { colors };
+        let entry_size = if header_size == CORE_HEADER_SIZE {
+            3
+        } else {
+            4
+        };
+        palette_size = entries as usize * entry_size;
+    }
+    if compression == BI_BITFIELDS && header_size == INFO_HEADER_SIZE {
+        palette_size += BITFIELDS_SIZE;
+    }
+    if offset > image.len() {
+        report.anomaly(format!("Pixel offset of {} exceeds the file", offset));
+        return;
+    }
+    if offset < header_end {
+        report.anomaly("Pixels overlap the header");
+    } else if offset > header_end {
+        report.part("Palette", header_end, offset - header_end);
+        if offset - header_end > palette_size {
+            report.anomaly(format!(
+                "{} unused bytes precede the pixels",
+                offset - header_end - palette_size
+            ));
+        }
+    }
+    report.part("Pixels", offset, image.len() - offset);
+    if header_size == V5_HEADER_SIZE && extract_u32_le(image, 70) == PROFILE_EMBEDDED {
+        // the profile offset is relative to the start of the header
+        let profile = FILE_HEADER_SIZE as u64 + extract_u32_le(image, 126) as u64;
+        let profile_size = extract_u32_le(image, 130) as u64;
+        report.field("Profile offset", profile);
+        report.field("Profile size", profile_size);
+        if profile + profile_size > image.len() as u64 {
+            report.anomaly(format!(
+                "ICC profile of {} bytes at {} exceeds the file",
+                profile_size, profile
+            ));
+        }
+    }
+
+    match compression {
+        BI_RLE8 | BI_RLE4 => match rle_pixels(image, compression) {
+            Ok(pixels) => report.plausible = Some(pixels),
+            Err(error) => report.anomaly(format!("{:#}", error)),
+        },
+        BI_RGB | BI_BITFIELDS => {
+            // rows are padded to 4 bytes
+            let width = width as u32 as u64;
+            let row_size = (width * bit_count as u64 + 31) / 32 * 4;
+            if row_size > 0 {
+                let rows = (image.len() - offset) as u64 / row_size;
+                report.plausible = Some(rows * width);
+            }
+        }
+        _ => {}
+    }
 }
@@ -0,0 +1,215 @@
+//! Decodes images using the `image` crate in a child process, whose address
+//! space and runtime are limited. How the child ends tells whether the image
+//! is mean enough to crash or stall a decoder without limits of its own.
+
+use crate::args::Verify;
+use ::image::GenericImageView;
+use common::{Context, Result};
+use std::{
+    env,
+    io::{self, Read},
+    mem,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const MIB: u64 = 1 << 20;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// Exit code of main returning an error
+const REFUSED_EXIT_CODE: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// The image was decoded
+    Succeeded,
+    /// The decoder returned an error
+    Refused,
+    /// The decoder panicked, aborted or was killed by a signal
+    Crashed,
+    /// The decoder was killed after the timeout
+    TimedOut,
+}
+
+const OUTCOMES: &[Outcome] = &[
+    Outcome::Succeeded,
+    Outcome::Refused,
+    Outcome::Crashed,
+    Outcome::TimedOut,
+];
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Refused => "refused",
+            Self::Crashed => "crashed",
+            Self::TimedOut => "timed out",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Report {
+    outcome: Outcome,
+    status: ExitStatus,
+    /// Peak resident set size in bytes
+    peak_rss: u64,
+    elapsed: Duration,
+    /// Output of the child, which explains the outcome
+    output: String,
+}
+
+/// Decodes the image without any limits. Runs in the child process, an
+/// error results in the exit code 1.
+pub(crate) fn decode(path: &Path) -> Result<()> {
+    let image = ::image::open(path).context("Unable to decode image")?;
+    let (width, height) = image.dimensions();
+    println!("Decoded {}x{} pixels", width, height);
+    Ok(())
+}
+
+/// Decodes every file in a child process and prints a report for each of
+/// them
+pub(crate) fn verify(files: &[PathBuf], args: &Verify) -> Result<()> {
+    println!(
+        "Decoding with {} MiB of address space and a timeout of {} s",
+        args.memory_limit, args.timeout
+    );
+    let mut outcomes = Vec::new();
+    for file in files {
+        let report = run(file, args)?;
+        print_report(file, &report);
+        outcomes.push(report.outcome);
+    }
+    if files.len() > 1 {
+        let summary: Vec<String> = OUTCOMES
+            .iter()
+            .map(|outcome| {
+                let count = outcomes.iter().filter(|other| *other == outcome).count();
+                format!("{} {}", count, outcome.name())
+            })
+            .collect();
+        println!("{} files: {}", files.len(), summary.join(", "));
+    }
+    Ok(())
+}
+
+fn run(file: &Path, args: &Verify) -> Result<Report> {
+    let executable = env::current_exe().context("Unable to find the executable")?;
+    let mut command = Command::new(executable);
+    let _ = command
+        .arg("decode")
+        .arg(file)
+        // keeps the output of panics and errors short
+        .env("RUST_BACKTRACE", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    limit_address_space(&mut command, args.memory_limit * MIB);
+
+    let timeout = Duration::from_secs(args.timeout);
+    let start = Instant::now();
+    let mut child = command.spawn().context("Unable to start the decoder")?;
+    // a full pipe would block the child until the timeout
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+    let pid = child.id() as libc::pid_t;
+    let (status, usage, timed_out) = loop {
+        if let Some((status, usage)) = wait(pid, libc::WNOHANG)? {
+            break (status, usage, false);
+        }
+        if start.elapsed() > timeout {
+            child.kill().context("Unable to kill the decoder")?;
+            let (status, usage) = wait(pid, 0)?.context("Decoder did not exit")?;
+            break (status, usage, true);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let elapsed = start.elapsed();
+
+    let mut output = String::new();
+    for reader in stdout.into_iter().chain(stderr) {
+        let text = reader
+            .join()
+            .ok()
+            .context("Unable to read the output of the decoder")?
+            .context("Unable to read the output of the decoder")?;
+        output.push_str(&text);
+    }
+
+    let status = ExitStatus::from_raw(status);
+    let outcome = if timed_out {
+        Outcome::TimedOut
+    } else {
+        match status.code() {
+            Some(0) => Outcome::Succeeded,
+            Some(REFUSED_EXIT_CODE) => Outcome::Refused,
+            _ => Outcome::Crashed,
+        }
+    };
+    Ok(Report {
+        outcome,
+        status,
+        // Linux reports kilobytes
+        peak_rss: usage.ru_maxrss as u64 * 1024,
+        elapsed,
+        output,
+    })
+}
+
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<io::Result<String>> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = pipe.read_to_string(&mut output)?;
+        Ok(output)
+    })
+}
+
+/// Sets RLIMIT_AS in the child, so allocations beyond it fail instead of
+/// exhausting the memory of the system
+#[allow(unsafe_code)]
+fn limit_address_space(command: &mut Command, bytes: u64) {
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+    // only calls setrlimit, which is async-signal-safe, between fork and exec
+    let _ = unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        })
+    };
+}
+
+/// Reaps the child using wait4, which unlike `Child::wait` reports its
+/// resource usage
+#[allow(unsafe_code)]
+fn wait(pid: libc::pid_t, options: libc::c_int) -> Result<Option<(i32, libc::rusage)>> {
+    let mut status = 0;
+    // rusage only consists of integers, for which zero is valid
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    match unsafe { libc::wait4(pid, &mut status, options, &mut usage) } {
+        -1 => Err(io::Error::last_os_error()).context("Unable to wait for the decoder"),
+        0 => Ok(None),
+        _ => Ok(Some((status, usage))),
+    }
+}
+
+fn print_report(file: &Path, report: &Report) {
+    println!("{}: {}", file.display(), report.outcome.name());
+    if let Some(signal) = report.status.signal() {
+        println!("  Signal: {}", signal);
+    }
+    println!("  Peak RSS: {:.1} MiB", report.peak_rss as f64 / MIB as f64);
+    println!("  Elapsed: {:.3} s", report.elapsed.as_secs_f64());
+    for line in report.output.lines().filter(|line| !line.trim().is_empty()) {
+        println!("  {}", line);
+    }
+}
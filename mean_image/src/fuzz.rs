@@ -0,0 +1,150 @@
+//! Structure-aware fuzzing. Every format splits its file into fields, which
+//! are mutated and put back together. Putting them back together fixes up
+//! lengths and checksums, so the mutations get past the integrity checks and
+//! reach the parsers behind them.
+
+use crate::args::Args;
+use common::{Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{fs, path::PathBuf};
+
+const INTERESTING_U8: &[u8] = &[0, 1, 0x7f, 0x80, 0xff];
+const INTERESTING_U16: &[u16] = &[0, 1, 0x7fff, 0x8000, 0xffff];
+const INTERESTING_U32: &[u32] = &[0, 1, 0x7fff_ffff, 0x8000_0000, 0xffff_ffff];
+const MAX_MUTATIONS: usize = 4;
+const MAX_FLIPPED_BITS: usize = 8;
+const MAX_GROWTH: usize = 1024;
+
+/// Part of a file like a PNG chunk or a JPEG segment. The tag identifies its
+/// type, the data excludes everything which is fixed up when the fields are
+/// put back together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Field {
+    pub(crate) tag: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Fields of a file and how to put them back together
+#[derive(Debug, Clone)]
+pub(crate) struct Structure {
+    pub(crate) fields: Vec<Field>,
+    /// Number of leading fields like headers, which keep their position
+    pub(crate) fixed: usize,
+    pub(crate) big_endian: bool,
+    pub(crate) assemble: fn(&[Field]) -> Result<Vec<u8>>,
+}
+
+/// Verifies that the fields reproduce the image and writes `args.count`
+/// mutations of it into the corpus directory
+pub(crate) fn write_corpus(image: &[u8], structure: &Structure, args: &Args) -> Result<()> {
+    assert_eq!(
+        image,
+        &(structure.assemble)(&structure.fields)?[..],
+        "Fields do not reproduce the image"
+    );
+    let seed = args.seed.context("Fuzzing requires a seed")?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let directory = args.corpus_path();
+    fs::create_dir_all(&directory).context("Unable to create corpus directory")?;
+    for path in corpus_files(args)? {
+        let mut fields = structure.fields.clone();
+        for _ in 0..rng.gen_range(1, MAX_MUTATIONS + 1) {
+            mutate(&mut fields, structure, &mut rng);
+        }
+        fs::write(path, (structure.assemble)(&fields)?).context("Unable to write to image file")?;
+    }
+    println!(
+        "Wrote {} files with {} fields each to {}",
+        args.count,
+        structure.fields.len(),
+        directory.display()
+    );
+    Ok(())
+}
+
+/// Numbered files of the corpus
+pub(crate) fn corpus_files(args: &Args) -> Result<Vec<PathBuf>> {
+    let extension = args.format()?.extension();
+    let directory = args.corpus_path();
+    Ok((0..args.count)
+        .map(|index| directory.join(format!("{:05}.{}", index, extension)))
+        .collect())
+}
+
+/// Applies a single mutation to a random field
+fn mutate(fields: &mut Vec<Field>, structure: &Structure, rng: &mut StdRng) {
+    let index = rng.gen_range(0, fields.len());
+    // only fields following the fixed ones may be moved, copied or removed
+    let movable = index >= structure.fixed;
+    match rng.gen_range(0, 8) {
+        0 => flip_bits(&mut fields[index].data, rng),
+        1 => write_interesting(&mut fields[index].data, structure.big_endian, rng),
+        2 => {
+            let len = fields[index].data.len();
+            let size = rng.gen_range(0, len + 1);
+            fields[index].data.truncate(size);
+        }
+        3 => {
+            let size = rng.gen_range(1, MAX_GROWTH + 1);
+            fields[index]
+                .data
+                .extend((0..size).map(|_| rng.gen::<u8>()));
+        }
+        4 if movable => {
+            let field = fields[index].clone();
+            fields.insert(index, field);
+        }
+        5 if movable && fields.len() > structure.fixed + 1 => {
+            let _ = fields.remove(index);
+        }
+        6 if movable => {
+            let other = rng.gen_range(structure.fixed, fields.len());
+            fields.swap(index, other);
+        }
+        7 if movable => {
+            // turns the field into another type found in the file
+            let tag = fields.choose(rng).map_or(0, |field| field.tag);
+            fields[index].tag = tag;
+        }
+        _ => flip_bits(&mut fields[index].data, rng),
+    }
+}
+
+fn flip_bits(data: &mut [u8], rng: &mut StdRng) {
+    if data.is_empty() {
+        return;
+    }
+    for _ in 0..rng.gen_range(1, MAX_FLIPPED_BITS + 1) {
+        let pos = rng.gen_range(0, data.len());
+        data[pos] ^= 1 << rng.gen_range(0, 8);
+    }
+}
+
+/// Overwrites a random position with a value at the edges of 8, 16 or 32 bit
+/// integers, in the byte order of the format
+fn write_interesting(data: &mut [u8], big_endian: bool, rng: &mut StdRng) {
+    let value = match rng.gen_range(0, 3) {
+        0 => vec![*INTERESTING_U8.choose(rng).unwrap_or(&0)],
+        1 => {
+            let value = *INTERESTING_U16.choose(rng).unwrap_or(&0);
+            if big_endian {
+                value.to_be_bytes().to_vec()
+            } else {
+                value.to_le_bytes().to_vec()
+            }
+        }
+        _ => {
+            let value = *INTERESTING_U32.choose(rng).unwrap_or(&0);
+            if big_endian {
+                value.to_be_bytes().to_vec()
+            } else {
+                value.to_le_bytes().to_vec()
+            }
+        }
+    };
+    if data.len() < value.len() {
+        return;
+    }
+    let pos = rng.gen_range(0, data.len() - value.len() + 1);
+    data[pos..pos + value.len()].copy_from_slice(&value);
+}
@@ -1,14 +1,154 @@
-use crate::{extract_u16_le, write_u16_le, HEIGHT, NEW_HEIGHT, NEW_WIDTH, WIDTH};
+use crate::{
+    args::{Args, Mode},
+    bits::BitWriter,
+    extract_u16_le,
+    fuzz::{self, Field, Structure},
+    inspect::{Report, MAX_PLAUSIBLE_COUNT},
+    polyglot::{self, Format, COMMENT_END, SCRIPT},
+    write_u16_le,
+};
 use common::{bail, Context, Result};
 use image::{gif::Encoder, EncodableLayout, ImageBuffer, Pixel, Rgb};
 use std::fs;
 
-pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<()> {
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::FrameBomb,
+    Mode::LzwCodeSize,
+    Mode::InfiniteAnimation,
+    Mode::Polyglot,
+    Mode::Fuzz,
+];
+
+const HEADER_SIZE: usize = 13;
+const EXTENSION: u8 = 0x21;
+const PLAIN_TEXT: u8 = 0x01;
+const GRAPHIC_CONTROL: u8 = 0xf9;
+const COMMENT: u8 = 0xfe;
+const APPLICATION: u8 = 0xff;
+const IMAGE_DESCRIPTOR: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+
+const FRAME_BOMB_COUNT: usize = 10_000;
+const ANIMATION_FRAME_COUNT: usize = 2;
+// Smallest code size allowed, so 2 colors are enough
+const MIN_CODE_SIZE: u8 = 2;
+const MAX_CODE_SIZE: u8 = 8;
+// Codes start with 13 bit while decoders only expect up to 12 bit
+const HUGE_CODE_SIZE: u8 = 12;
+const MAX_CODE_WIDTH: u32 = 12;
+const MAX_DICTIONARY_SIZE: u32 = 1 << MAX_CODE_WIDTH;
+const MAX_SUB_BLOCK_SIZE: usize = 255;
+// Tags the header and Global Color Table, extensions are tagged with their
+// introducer and label
+const HEADER: u32 = 0;
+
+// Opens a JavaScript block comment right after the GIF89a signature
+const SCRIPT_WIDTH: u16 = u16::from_le_bytes(*b"/*");
+
+/// Structure of a GIF file
+#[derive(Debug, Clone)]
+pub(crate) struct Gif {
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    /// Size of the Global Color Table in bytes
+    pub(crate) global_color_table: usize,
+    pub(crate) blocks: Vec<Block>,
+    /// Position of the trailer
+    pub(crate) trailer: usize,
+}
+
+impl Gif {
+    pub(crate) fn images(&self) -> impl Iterator<Item = Descriptor> + '_ {
+        self.blocks.iter().filter_map(|block| match *block {
+            Block::Image(descriptor) => Some(descriptor),
+            Block::Extension(_) => None,
+        })
+    }
+
+    pub(crate) fn extensions(&self) -> impl Iterator<Item = Extension> + '_ {
+        self.blocks.iter().filter_map(|block| match *block {
+            Block::Extension(extension) => Some(extension),
+            Block::Image(_) => None,
+        })
+    }
+}
+
+impl Block {
+    fn pos(self) -> usize {
+        match self {
+            Self::Extension(extension) => extension.pos,
+            Self::Image(descriptor) => descriptor.pos,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Block {
+    Extension(Extension),
+    Image(Descriptor),
+}
+
+/// Extension consisting of its label and sub-blocks
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Extension {
+    pub(crate) pos: usize,
+    pub(crate) label: u8,
+}
+
+/// Image Descriptor followed by the Local Color Table and image data
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Descriptor {
+    pub(crate) pos: usize,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    /// Size of the Local Color Table in bytes
+    pub(crate) local_color_table: usize,
+}
+
+impl Descriptor {
+    pub(crate) fn code_size_pos(self) -> usize {
+        self.pos + 10 + self.local_color_table
+    }
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
     let mut image = create_gif(image)?;
-    verify_image(&image, WIDTH, HEIGHT)?;
-    modify_width_and_height(&mut image, NEW_WIDTH, NEW_HEIGHT)?;
-    verify_image(&image, NEW_WIDTH, NEW_HEIGHT)?;
-    fs::write("output.gif", image).context("Unable to write to image file")?;
+    verify_image(&image, args.width, args.height)?;
+    match args.mode {
+        Mode::Dimensions => {
+            let (new_width, new_height) = args.new_dimensions_u16()?;
+            modify_width_and_height(&mut image, new_width, new_height)?;
+            verify_image(&image, new_width, new_height)?;
+        }
+        Mode::FrameBomb => {
+            let start = image.len() - 1;
+            image = append_frames(&image, FRAME_BOMB_COUNT, None);
+            verify_image(&image, args.width, args.height)?;
+            verify_frames(&image, start, FRAME_BOMB_COUNT)?;
+        }
+        Mode::LzwCodeSize => {
+            modify_code_size(&mut image)?;
+            verify_image(&image, args.width, args.height)?;
+            verify_code_size(&image)?;
+        }
+        Mode::InfiniteAnimation => {
+            image = insert_loop(&image)?;
+            let start = image.len() - 1;
+            image = append_frames(&image, ANIMATION_FRAME_COUNT, Some(0));
+            verify_image(&image, args.width, args.height)?;
+            verify_loop(&image)?;
+            verify_frames(&image, start, ANIMATION_FRAME_COUNT)?;
+        }
+        Mode::Polyglot => {
+            image = create_script(&image)?;
+            verify_script(&image, args.width, args.height)?;
+            polyglot::verify_formats(&image, &[Format::Gif, Format::JavaScript])?;
+        }
+        Mode::Fuzz => return fuzz::write_corpus(&image, &structure(&image)?, args),
+        _ => bail!("Mode is not supported by GIF"),
+    }
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
     Ok(())
 }
 
@@ -27,84 +167,616 @@ fn create_gif(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-#[allow(clippy::shadow_same)]
-fn skip_global_color_table(image: &[u8], pos: usize) -> Result<usize> {
-    let mut pos = pos;
-    loop {
-        if pos > image.len() {
-            bail!("Unable to find Local Image Descriptor");
-        }
-        match image[pos] {
-            0x21 | 0x2c => return Ok(pos), // Start of Extension | Local Image Descriptor
-            _ => {}
-        }
-        pos += 3;
+/// Parses the structure of the file by walking all blocks
+pub(crate) fn parse(image: &[u8]) -> Result<Gif> {
+    if image.len() < HEADER_SIZE {
+        bail!("Header exceeds the file");
     }
-}
-
-#[allow(clippy::shadow_same)]
-fn skip_extensions(image: &[u8], pos: usize) -> Result<usize> {
-    let mut pos = pos;
+    let global_color_table = color_table_size(image[10]);
+    let mut pos = HEADER_SIZE + global_color_table;
+    let mut blocks = Vec::new();
     loop {
-        if pos + 1 > image.len() {
-            bail!("Unable to find Local Image Descriptor");
-        }
-        let introducer = image[pos];
-        let label = image[pos + 1];
-        match introducer {
-            0x21 => match label {
-                // Plain Text Extension
-                0x01 => pos += 17,
-                // Graphics Control Extension
-                0xf9 => pos += 8,
-                // Comment Extension block
-                0xfe => pos += 4,
-                // Application Extension block
-                0xff => pos += 16,
-                _ => bail!("Invalid label"),
-            },
-            0x2c => return Ok(pos),
-            _ => bail!("Invalid introducer"),
+        match image.get(pos) {
+            Some(&EXTENSION) => {
+                let label = *image.get(pos + 1).context("Extension exceeds the file")?;
+                blocks.push(Block::Extension(Extension { pos, label }));
+                pos = skip_sub_blocks(image, pos + 2)?;
+            }
+            Some(&IMAGE_DESCRIPTOR) => {
+                if pos + 10 > image.len() {
+                    bail!("Image Descriptor exceeds the file");
+                }
+                let descriptor = Descriptor {
+                    pos,
+                    width: extract_u16_le(image, pos + 5),
+                    height: extract_u16_le(image, pos + 7),
+                    local_color_table: color_table_size(image[pos + 9]),
+                };
+                let code_size = descriptor.code_size_pos();
+                if code_size >= image.len() {
+                    bail!("Image data exceeds the file");
+                }
+                blocks.push(Block::Image(descriptor));
+                pos = skip_sub_blocks(image, code_size + 1)?;
+            }
+            Some(&TRAILER) => {
+                return Ok(Gif {
+                    width: extract_u16_le(image, 6),
+                    height: extract_u16_le(image, 8),
+                    global_color_table,
+                    blocks,
+                    trailer: pos,
+                })
+            }
+            Some(introducer) => bail!("Invalid introducer {:#04x} at {}", introducer, pos),
+            None => bail!("Unable to find Trailer"),
         }
     }
 }
 
 #[allow(clippy::panic)]
 fn verify_image(image: &[u8], width: u16, height: u16) -> Result<()> {
+    let gif = parse(image)?;
     assert_eq!(b"GIF", &image[0..3], "GIF Signature is not valid");
     assert!(
         b"87a" == &image[3..6] || b"89a" == &image[3..6],
         "GIF Version is not valid"
     );
-    let img_width = extract_u16_le(image, 6);
-    let img_height = extract_u16_le(image, 8);
-    assert_eq!(width, img_width, "Image width is invalid");
-    assert_eq!(height, img_height, "Image height is invalid");
-
-    let pos = skip_global_color_table(image, 13)?;
-    let mut pos = skip_extensions(image, pos)?;
+    assert_eq!(width, gif.width, "Image width is invalid");
+    assert_eq!(height, gif.height, "Image height is invalid");
 
-    while image[pos] == 0x2c {
-        let img_width = extract_u16_le(image, pos + 5);
-        let img_height = extract_u16_le(image, pos + 7);
-        assert_eq!(width, img_width, "Image width is invalid");
-        assert_eq!(height, img_height, "Image height is invalid");
-        pos += 10;
+    for descriptor in gif.images() {
+        assert_eq!(width, descriptor.width, "Image width is invalid");
+        assert_eq!(height, descriptor.height, "Image height is invalid");
     }
     Ok(())
 }
 
 fn modify_width_and_height(image: &mut [u8], new_width: u16, new_height: u16) -> Result<()> {
+    let gif = parse(image)?;
     write_u16_le(image, 6, new_width);
     write_u16_le(image, 8, new_height);
 
-    let pos = skip_global_color_table(image, 13)?;
-    let mut pos = skip_extensions(image, pos)?;
+    for descriptor in gif.images() {
+        write_u16_le(image, descriptor.pos + 5, new_width);
+        write_u16_le(image, descriptor.pos + 7, new_height);
+    }
+    Ok(())
+}
+
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 0x07) + 1)
+    }
+}
+
+/// LZW stream of `pixels` times the color 0. Every code is one pixel longer
+/// than the previous one until the dictionary is full.
+fn compress_zeros(pixels: u64) -> Vec<u8> {
+    let clear = 1_u32 << MIN_CODE_SIZE;
+    let end_of_information = clear + 1;
+    let mut next = clear + 2;
+    let mut width = MIN_CODE_SIZE as u32 + 1;
+    let mut length = 1_u64;
+
+    let mut writer = BitWriter::default();
+    writer.write(clear, width);
+    let mut remaining = pixels;
+    while remaining > 0 {
+        let current = length.min(remaining);
+        // the string of n zeros has the code clear + n
+        let code = if current == 1 {
+            0
+        } else {
+            clear + current as u32
+        };
+        writer.write(code, width);
+        remaining -= current;
+        if next < MAX_DICTIONARY_SIZE {
+            next += 1;
+            length += 1;
+            if next > 1 << width && width < MAX_CODE_WIDTH {
+                width += 1;
+            }
+        }
+    }
+    writer.write(end_of_information, width);
+    writer.into_bytes()
+}
+
+/// Full screen frame with a black and white local color table, which is
+/// completely black
+fn create_frame(width: u16, height: u16, delay: Option<u16>) -> Vec<u8> {
+    let mut frame = Vec::new();
+    if let Some(delay) = delay {
+        frame.extend_from_slice(&[EXTENSION, GRAPHIC_CONTROL, 4, 0]);
+        frame.extend_from_slice(&delay.to_le_bytes());
+        frame.extend_from_slice(&[0, 0]);
+    }
+    frame.extend_from_slice(&[IMAGE_DESCRIPTOR, 0, 0, 0, 0]);
+    frame.extend_from_slice(&width.to_le_bytes());
+    frame.extend_from_slice(&height.to_le_bytes());
+    frame.extend_from_slice(&[0x80, 0, 0, 0, 0xff, 0xff, 0xff, MIN_CODE_SIZE]);
+    for block in compress_zeros(width as u64 * height as u64).chunks(MAX_SUB_BLOCK_SIZE) {
+        frame.push(block.len() as u8);
+        frame.extend_from_slice(block);
+    }
+    frame.push(0);
+    frame
+}
+
+/// Appends the frames in front of the trailer
+fn append_frames(image: &[u8], count: usize, delay: Option<u16>) -> Vec<u8> {
+    let frame = create_frame(extract_u16_le(image, 6), extract_u16_le(image, 8), delay);
+    let mut output = image[..image.len() - 1].to_vec();
+    for _ in 0..count {
+        output.extend_from_slice(&frame);
+    }
+    output.push(TRAILER);
+    output
+}
+
+fn skip_sub_blocks(image: &[u8], pos: usize) -> Result<usize> {
+    let mut pos = pos;
+    loop {
+        if pos >= image.len() {
+            bail!("Sub-blocks exceed the file");
+        }
+        let size = image[pos] as usize;
+        pos += 1 + size;
+        if size == 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+/// The header and every block is a field. Extensions keep their sub-blocks,
+/// the image data is split into sub-blocks again.
+fn structure(image: &[u8]) -> Result<Structure> {
+    let gif = parse(image)?;
+    let mut fields = vec![Field {
+        tag: HEADER,
+        data: image[..HEADER_SIZE + gif.global_color_table].to_vec(),
+    }];
+    for (index, block) in gif.blocks.iter().enumerate() {
+        let end = gif
+            .blocks
+            .get(index + 1)
+            .map_or(gif.trailer, |next| match *next {
+                Block::Extension(extension) => extension.pos,
+                Block::Image(descriptor) => descriptor.pos,
+            });
+        fields.push(match *block {
+            Block::Extension(extension) => Field {
+                tag: u32::from(EXTENSION) << 8 | u32::from(extension.label),
+                data: image[extension.pos + 2..end].to_vec(),
+            },
+            Block::Image(descriptor) => {
+                let code_size = descriptor.code_size_pos();
+                let mut data = image[descriptor.pos + 1..=code_size].to_vec();
+                data.extend(join_sub_blocks(&image[code_size + 1..end]));
+                Field {
+                    tag: IMAGE_DESCRIPTOR.into(),
+                    data,
+                }
+            }
+        });
+    }
+    Ok(Structure {
+        fields,
+        fixed: 1,
+        big_endian: false,
+        assemble: assemble_blocks,
+    })
+}
+
+fn assemble_blocks(fields: &[Field]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    for field in fields {
+        if field.tag == u32::from(IMAGE_DESCRIPTOR) {
+            output.push(IMAGE_DESCRIPTOR);
+            // descriptor without introducer, color table and code size
+            let header = match field.data.get(8) {
+                Some(&packed) => (9 + color_table_size(packed) + 1).min(field.data.len()),
+                None => field.data.len(),
+            };
+            output.extend_from_slice(&field.data[..header]);
+            for block in field.data[header..].chunks(MAX_SUB_BLOCK_SIZE) {
+                output.push(block.len() as u8);
+                output.extend_from_slice(block);
+            }
+            output.push(0);
+        } else if field.tag >> 8 == u32::from(EXTENSION) {
+            output.extend_from_slice(&[EXTENSION, field.tag as u8]);
+            output.extend_from_slice(&field.data);
+        } else {
+            output.extend_from_slice(&field.data);
+        }
+    }
+    output.push(TRAILER);
+    Ok(output)
+}
+
+fn verify_frames(image: &[u8], start: usize, count: usize) -> Result<()> {
+    let gif = parse(image)?;
+    let frames: Vec<Descriptor> = gif.images().filter(|frame| frame.pos >= start).collect();
+    assert_eq!(count, frames.len(), "Frame count is invalid");
+    for frame in frames {
+        assert_eq!(gif.width, frame.width, "Frame width is invalid");
+        assert_eq!(gif.height, frame.height, "Frame height is invalid");
+        let code_size = image[frame.code_size_pos()];
+        assert_eq!(MIN_CODE_SIZE, code_size, "LZW code size is invalid");
+    }
+    for control in gif
+        .extensions()
+        .filter(|extension| extension.pos >= start && extension.label == GRAPHIC_CONTROL)
+    {
+        assert_eq!(
+            0,
+            extract_u16_le(image, control.pos + 4),
+            "Frame delay is invalid"
+        );
+    }
+    assert_eq!(image.len(), gif.trailer + 1, "Data follows the trailer");
+    Ok(())
+}
+
+fn code_size_pos(image: &[u8]) -> Result<usize> {
+    let gif = parse(image)?;
+    let descriptor = gif
+        .images()
+        .next()
+        .context("Unable to find Image Descriptor")?;
+    Ok(descriptor.code_size_pos())
+}
+
+fn verify_code_size(image: &[u8]) -> Result<()> {
+    let pos = code_size_pos(image)?;
+    assert_eq!(HUGE_CODE_SIZE, image[pos], "LZW code size is invalid");
+    Ok(())
+}
+
+fn modify_code_size(image: &mut [u8]) -> Result<()> {
+    let pos = code_size_pos(image)?;
+    image[pos] = HUGE_CODE_SIZE;
+    Ok(())
+}
+
+fn verify_loop(image: &[u8]) -> Result<()> {
+    let application = parse(image)?
+        .extensions()
+        .find(|extension| extension.label == APPLICATION)
+        .context("Unable to find Application Extension")?;
+    let pos = application.pos;
+    assert_eq!(11, image[pos + 2], "Application Extension is invalid");
+    assert_eq!(
+        b"NETSCAPE2.0",
+        &image[pos + 3..pos + 14],
+        "Application is invalid"
+    );
+    assert_eq!(0, extract_u16_le(image, pos + 16), "Loop count is invalid");
+    Ok(())
+}
+
+/// Inserts a NETSCAPE2.0 Application Extension which loops forever
+fn insert_loop(image: &[u8]) -> Result<Vec<u8>> {
+    let pos = HEADER_SIZE + parse(image)?.global_color_table;
+    let mut output = image[..pos].to_vec();
+    output.extend_from_slice(&[EXTENSION, APPLICATION, 11]);
+    output.extend_from_slice(b"NETSCAPE2.0");
+    // sub-block with the loop count 0, which means forever
+    output.extend_from_slice(&[3, 1, 0, 0, 0]);
+    output.extend_from_slice(&image[pos..]);
+    Ok(output)
+}
+
+/// Turns the GIF into JavaScript. A Comment Extension contains the script,
+/// everything else ends up within block comments.
+fn create_script(image: &[u8]) -> Result<Vec<u8>> {
+    let gif = parse(image)?;
+    let start = HEADER_SIZE + gif.global_color_table;
+    let mut output = image[..start].to_vec();
+    scrub_comment_ends(&mut output[HEADER_SIZE..]);
+    output[3..6].copy_from_slice(b"89a");
+    write_u16_le(&mut output, 6, SCRIPT_WIDTH);
+    output.extend_from_slice(&[EXTENSION, COMMENT, SCRIPT.len() as u8]);
+    output.extend_from_slice(SCRIPT);
+    output.push(0);
+
+    for block in &gif.blocks {
+        match *block {
+            Block::Extension(extension) => {
+                let end = skip_sub_blocks(image, extension.pos + 2)?;
+                output.extend_from_slice(&image[extension.pos..extension.pos + 2]);
+                write_script_sub_blocks(&mut output, &sub_blocks(&image[extension.pos + 2..end]));
+            }
+            Block::Image(descriptor) => {
+                let data = descriptor.code_size_pos() + 1;
+                let mut header = image[descriptor.pos..data].to_vec();
+                scrub_comment_ends(&mut header[10..]);
+                output.extend(header);
+                let end = skip_sub_blocks(image, data)?;
+                write_script_sub_blocks(&mut output, &[&join_sub_blocks(&image[data..end])]);
+            }
+        }
+    }
+    output.push(TRAILER);
+    output.extend_from_slice(COMMENT_END);
+    Ok(output)
+}
+
+fn sub_blocks(blocks: &[u8]) -> Vec<&[u8]> {
+    let mut data = Vec::new();
+    let mut pos = 0;
+    while blocks[pos] != 0 {
+        let size = blocks[pos] as usize;
+        data.push(&blocks[pos + 1..pos + 1 + size]);
+        pos += 1 + size;
+    }
+    data
+}
 
-    while image[pos] == 0x2c {
-        write_u16_le(image, pos + 5, new_width);
-        write_u16_le(image, pos + 7, new_height);
-        pos += 10;
+fn join_sub_blocks(blocks: &[u8]) -> Vec<u8> {
+    sub_blocks(blocks).concat()
+}
+
+/// Slightly changes colors of a color table which would end the comment
+fn scrub_comment_ends(table: &mut [u8]) {
+    for index in 0..table.len().saturating_sub(1) {
+        if &table[index..index + 2] == COMMENT_END {
+            table[index] += 1;
+        }
     }
+}
+
+/// Writes the data of every sub-block again and splits it where needed, so
+/// length bytes separate every "*/" and never form one with the data around
+/// them
+fn write_script_sub_blocks(output: &mut Vec<u8>, blocks: &[&[u8]]) {
+    for data in blocks {
+        let mut rest = *data;
+        while !rest.is_empty() {
+            let mut size = rest.len().min(MAX_SUB_BLOCK_SIZE);
+            if let Some(end) = polyglot::find(&rest[..size], COMMENT_END) {
+                size = end + 1;
+            }
+            while (output.last() == Some(&COMMENT_END[0]) && size == COMMENT_END[1] as usize)
+                || (size == COMMENT_END[0] as usize && rest[0] == COMMENT_END[1])
+            {
+                size -= 1;
+            }
+            output.push(size as u8);
+            output.extend_from_slice(&rest[..size]);
+            rest = &rest[size..];
+        }
+    }
+    output.push(0);
+}
+
+fn verify_script(image: &[u8], width: u16, height: u16) -> Result<()> {
+    let gif = parse(image)?;
+    assert_eq!(b"GIF89a/*", &image[0..8], "GIF Signature is not valid");
+    assert_eq!(SCRIPT_WIDTH, gif.width, "Screen width is invalid");
+    for descriptor in gif.images() {
+        assert_eq!(width, descriptor.width, "Image width is invalid");
+        assert_eq!(height, descriptor.height, "Image height is invalid");
+    }
+    let comment = gif
+        .extensions()
+        .find(|extension| extension.label == COMMENT)
+        .context("Unable to find Comment Extension")?;
+    assert_eq!(
+        SCRIPT,
+        &image[comment.pos + 3..comment.pos + 3 + SCRIPT.len()],
+        "Script is invalid"
+    );
     Ok(())
 }
+
+/// Describes the Logical Screen, every block and how many pixels the image
+/// data of the frames holds
+pub(crate) fn inspect(image: &[u8], report: &mut Report) {
+    let gif = match parse(image) {
+        Ok(gif) => gif,
+        Err(error) => {
+            report.anomaly(format!("{:#}", error));
+            return;
+        }
+    };
+    let version = &image[3..6];
+    report.field("Version", String::from_utf8_lossy(version));
+    report.field("Screen width", gif.width);
+    report.field("Screen height", gif.height);
+    report.field("Global Color Table", gif.global_color_table);
+    report.claimed = Some((gif.width.into(), gif.height.into()));
+    if version != b"87a" && version != b"89a" {
+        report.anomaly("GIF Version is not valid");
+    }
+
+    report.part("Header", 0, HEADER_SIZE);
+    if gif.global_color_table > 0 {
+        report.part("Global Color Table", HEADER_SIZE, gif.global_color_table);
+    }
+    let mut plausible = 0;
+    let mut frames = 0;
+    for (index, &block) in gif.blocks.iter().enumerate() {
+        let end = gif
+            .blocks
+            .get(index + 1)
+            .map_or(gif.trailer, |next| next.pos());
+        match block {
+            Block::Extension(extension) => {
+                report.part(
+                    extension_name(extension.label),
+                    extension.pos,
+                    end - extension.pos,
+                );
+                let pos = extension.pos;
+                if extension.label == APPLICATION
+                    && image.get(pos + 3..pos + 14) == Some(&b"NETSCAPE2.0"[..])
+                    && pos + 18 <= end
+                {
+                    let loops = extract_u16_le(image, pos + 16);
+                    if loops == 0 {
+                        report.field("Loop count", "0, forever");
+                    } else {
+                        report.field("Loop count", loops);
+                    }
+                }
+            }
+            Block::Image(descriptor) => {
+                frames += 1;
+                report.part("Image", descriptor.pos, end - descriptor.pos);
+                let pos = descriptor.pos;
+                let (left, top) = (
+                    extract_u16_le(image, pos + 1),
+                    extract_u16_le(image, pos + 3),
+                );
+                if left as u32 + descriptor.width as u32 > gif.width as u32
+                    || top as u32 + descriptor.height as u32 > gif.height as u32
+                {
+                    report.anomaly(format!(
+                        "Frame {} of {}x{} at {},{} exceeds the screen",
+                        frames, descriptor.width, descriptor.height, left, top
+                    ));
+                }
+                let code_size = image[descriptor.code_size_pos()];
+                if !(MIN_CODE_SIZE..=MAX_CODE_SIZE).contains(&code_size) {
+                    report.anomaly(format!(
+                        "Frame {} has an LZW code size of {}",
+                        frames, code_size
+                    ));
+                }
+                // every code takes at least the initial code width and adds
+                // at most one dictionary entry worth of pixels
+                let data = join_sub_blocks(&image[descriptor.code_size_pos() + 1..]);
+                let codes = data.len() as u64 * 8 / (code_size as u64 + 1);
+                let pixels = codes * MAX_DICTIONARY_SIZE as u64;
+                let claimed = descriptor.width as u64 * descriptor.height as u64;
+                if claimed > pixels {
+                    report.anomaly(format!(
+                        "Frame {} claims {} pixels, but its image data holds at most {}",
+                        frames, claimed, pixels
+                    ));
+                }
+                plausible += pixels;
+            }
+        }
+    }
+    report.part("Trailer", gif.trailer, 1);
+    report.field("Frames", frames);
+    report.plausible = Some(plausible);
+    if frames == 0 {
+        report.anomaly("Image Descriptor is missing");
+    } else if frames > MAX_PLAUSIBLE_COUNT {
+        report.anomaly(format!("File contains {} frames", frames));
+    }
+    if gif.trailer + 1 < image.len() {
+        report.anomaly(format!(
+            "{} bytes follow the Trailer",
+            image.len() - gif.trailer - 1
+        ));
+    }
+}
+
+fn extension_name(label: u8) -> &'static str {
+    match label {
+        PLAIN_TEXT => "Plain Text Extension",
+        GRAPHIC_CONTROL => "Graphic Control Extension",
+        COMMENT => "Comment Extension",
+        APPLICATION => "Application Extension",
+        _ => "Unknown Extension",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_frames, create_gif, insert_loop, modify_width_and_height, parse, APPLICATION,
+        GRAPHIC_CONTROL, HEADER_SIZE,
+    };
+    use crate::image::generate_image;
+
+    fn create() -> Vec<u8> {
+        create_gif(&generate_image(5, 3, 1)).expect("Unable to create GIF")
+    }
+
+    #[test]
+    fn parse_created_gif() {
+        let image = create();
+        let gif = parse(&image).expect("Unable to parse GIF");
+        assert_eq!((5, 3), (gif.width, gif.height), "Screen is invalid");
+        assert_eq!(image.len() - 1, gif.trailer, "Trailer is invalid");
+        let images: Vec<_> = gif.images().collect();
+        assert_eq!(1, images.len(), "Image count is invalid");
+        assert_eq!(
+            (5, 3),
+            (images[0].width, images[0].height),
+            "Image is invalid"
+        );
+    }
+
+    #[test]
+    fn parse_inserted_blocks() {
+        let image = insert_loop(&append_frames(&create(), 2, Some(10))).expect("Unable to loop");
+        let gif = parse(&image).expect("Unable to parse GIF");
+        let first = gif.extensions().next().expect("Extension is missing");
+        assert_eq!(APPLICATION, first.label, "Loop is not the first block");
+        assert_eq!(
+            HEADER_SIZE + gif.global_color_table,
+            first.pos,
+            "Loop position is invalid"
+        );
+        assert_eq!(3, gif.images().count(), "Image count is invalid");
+        let delays = gif
+            .extensions()
+            .filter(|extension| extension.label == GRAPHIC_CONTROL)
+            .count();
+        assert!(delays >= 2, "Graphic Control Extensions are missing");
+        assert_eq!(image.len() - 1, gif.trailer, "Trailer is invalid");
+    }
+
+    #[test]
+    fn parse_modified_dimensions() {
+        let mut image = create();
+        modify_width_and_height(&mut image, 0xffff, 0x8000).expect("Unable to modify GIF");
+        let gif = parse(&image).expect("Unable to parse GIF");
+        assert_eq!(
+            (0xffff, 0x8000),
+            (gif.width, gif.height),
+            "Screen is invalid"
+        );
+        for descriptor in gif.images() {
+            assert_eq!(
+                (0xffff, 0x8000),
+                (descriptor.width, descriptor.height),
+                "Image is invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_truncated_gif() {
+        let image = create();
+        for length in 0..image.len() {
+            assert!(
+                parse(&image[..length]).is_err(),
+                "GIF truncated to {} bytes parses",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_introducer() {
+        let mut image = create();
+        let pos = HEADER_SIZE
+            + parse(&image)
+                .expect("Unable to parse GIF")
+                .global_color_table;
+        image[pos] = 0;
+        assert!(parse(&image).is_err(), "Invalid introducer parses");
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    args::{Args, ImageFormat, Mode},
+    bmp, extract_u16_le, extract_u32_le, png, write_u16_le, write_u32_le,
+};
+use common::{bail, Context, Result};
+use image::{ImageBuffer, Rgb};
+use std::fs;
+
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::EntrySize,
+    Mode::OverlappingOffsets,
+    Mode::EntryCount,
+];
+
+const HEADER_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 16;
+// Size of the BMP file header which is omitted within ICO files
+const BMP_FILE_HEADER_SIZE: usize = 14;
+
+const ICON: u16 = 1;
+const CURSOR: u16 = 2;
+
+// Entries pointing to the embedded PNG
+const HUGE_ENTRY_COUNT: u16 = u16::max_value();
+
+/// Entry of the ICO directory
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    pos: usize,
+    width: u8,
+    height: u8,
+    size: u32,
+    offset: u32,
+}
+
+/// Image embedded into the ICO file
+#[derive(Debug, Clone, Copy)]
+enum Embedded {
+    Png,
+    Bmp,
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let image_type = if args.format()? == &ImageFormat::CUR {
+        CURSOR
+    } else {
+        ICON
+    };
+    let entry_count = if args.mode == Mode::EntryCount {
+        HUGE_ENTRY_COUNT
+    } else {
+        2
+    };
+    let mut image = create_ico(image, image_type, entry_count)?;
+    let (width, height) = (args.width.into(), args.height.into());
+    verify_image(&image, image_type, width, height)?;
+    match args.mode {
+        Mode::Dimensions => {
+            let (new_width, new_height) = args.new_dimensions_u32();
+            modify_width_and_height(&mut image, new_width, new_height)?;
+            verify_image(&image, image_type, new_width, new_height)?;
+        }
+        Mode::EntrySize => {
+            modify_entry_sizes(&mut image)?;
+            verify_entry_sizes(&image)?;
+        }
+        Mode::OverlappingOffsets => {
+            modify_offsets(&mut image)?;
+            verify_offsets(&image)?;
+        }
+        Mode::EntryCount => {
+            verify_entry_count(&image)?;
+        }
+        _ => bail!("Mode is not supported by ICO"),
+    }
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
+    Ok(())
+}
+
+/// Creates an ICO or CUR file containing the image once as PNG and once as
+/// BMP. Additional entries point to the PNG again.
+fn create_ico(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    image_type: u16,
+    entry_count: u16,
+) -> Result<Vec<u8>> {
+    let png = png::create_png(image)?;
+    let bmp = create_dib(image)?;
+    let (width, height) = (image.width(), image.height());
+
+    let mut output = vec![0; HEADER_SIZE + entry_count as usize * ENTRY_SIZE];
+    write_u16_le(&mut output, 2, image_type);
+    write_u16_le(&mut output, 4, entry_count);
+
+    let png_offset = output.len();
+    output.extend_from_slice(&png);
+    let bmp_offset = output.len();
+    output.extend_from_slice(&bmp);
+
+    for index in 0..entry_count as usize {
+        let (offset, size) = if index == 1 {
+            (bmp_offset, bmp.len())
+        } else {
+            (png_offset, png.len())
+        };
+        let pos = HEADER_SIZE + index * ENTRY_SIZE;
+        // 0 means 256 or more pixels
+        output[pos] = if width < 256 { width as u8 } else { 0 };
+        output[pos + 1] = if height < 256 { height as u8 } else { 0 };
+        if image_type == CURSOR {
+            // hotspot in the center
+            write_u16_le(&mut output, pos + 4, (width / 2) as u16);
+            write_u16_le(&mut output, pos + 6, (height / 2) as u16);
+        } else {
+            write_u16_le(&mut output, pos + 4, 1);
+            write_u16_le(&mut output, pos + 6, 24);
+        }
+        write_u32_le(&mut output, pos + 8, size as u32);
+        write_u32_le(&mut output, pos + 12, offset as u32);
+    }
+    Ok(output)
+}
+
+/// BMP without file header whose height covers the color and the AND mask
+fn create_dib(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let mut dib = bmp::create_bmp(image)?.split_off(BMP_FILE_HEADER_SIZE);
+    write_u32_le(&mut dib, 8, image.height() * 2);
+    // rows of the 1 bit mask are padded to 4 bytes, all pixels are opaque
+    let row_size = (image.width() as usize + 31) / 32 * 4;
+    dib.resize(dib.len() + row_size * image.height() as usize, 0);
+    Ok(dib)
+}
+
+fn read_entries(image: &[u8]) -> Result<Vec<Entry>> {
+    let count = extract_u16_le(image, 4) as usize;
+    if HEADER_SIZE + count * ENTRY_SIZE > image.len() {
+        bail!("ICO directory exceeds the file");
+    }
+    Ok((0..count)
+        .map(|index| {
+            let pos = HEADER_SIZE + index * ENTRY_SIZE;
+            Entry {
+                pos,
+                width: image[pos],
+                height: image[pos + 1],
+                size: extract_u32_le(image, pos + 8),
+                offset: extract_u32_le(image, pos + 12),
+            }
+        })
+        .collect())
+}
+
+fn embedded_image(image: &[u8], entry: Entry) -> Result<(Embedded, usize)> {
+    let start = entry.offset as usize;
+    if start + entry.size as usize > image.len() {
+        bail!("Embedded image exceeds the file");
+    }
+    if image[start..].starts_with(png::SIGNATURE) {
+        Ok((Embedded::Png, start))
+    } else if extract_u32_le(image, start) == 40 {
+        Ok((Embedded::Bmp, start))
+    } else {
+        bail!("Embedded image is neither PNG nor BMP")
+    }
+}
+
+fn verify_image(image: &[u8], image_type: u16, width: u32, height: u32) -> Result<()> {
+    assert_eq!(0, extract_u16_le(image, 0), "ICO Reserved field is not 0");
+    assert_eq!(image_type, extract_u16_le(image, 2), "ICO Type is invalid");
+    for entry in read_entries(image)? {
+        match embedded_image(image, entry)? {
+            (Embedded::Png, start) => png::verify_image(&image[start..], width, height),
+            (Embedded::Bmp, start) => {
+                let img_width = extract_u32_le(image, start + 4);
+                let img_height = extract_u32_le(image, start + 8);
+                assert_eq!(width, img_width, "Image width is invalid");
+                assert_eq!(
+                    height.wrapping_mul(2),
+                    img_height,
+                    "Image height is invalid"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Claims the dimensions in the embedded images while the directory keeps
+/// the real ones
+fn modify_width_and_height(image: &mut [u8], new_width: u32, new_height: u32) -> Result<()> {
+    for entry in read_entries(image)? {
+        match embedded_image(image, entry)? {
+            (Embedded::Png, start) => {
+                png::modify_width_and_height(&mut image[start..], new_width, new_height);
+            }
+            (Embedded::Bmp, start) => {
+                write_u32_le(image, start + 4, new_width);
+                write_u32_le(image, start + 8, new_height.wrapping_mul(2));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Width and height decoders read from the embedded BMP, whose DIB stores
+/// twice the height
+pub(crate) fn bmp_dimensions(new_width: u32, new_height: u32) -> (i32, i32) {
+    let (width, height) = bmp::signed_dimensions(new_width, new_height.wrapping_mul(2));
+    (width, height / 2)
+}
+
+fn verify_entry_sizes(image: &[u8]) -> Result<()> {
+    for entry in read_entries(image)? {
+        assert_eq!(1, entry.width, "Entry width is invalid");
+        assert_eq!(1, entry.height, "Entry height is invalid");
+        assert_eq!(u32::max_value(), entry.size, "Entry size is invalid");
+    }
+    Ok(())
+}
+
+/// Directory entries claim 1x1 pixel images with more data than the file
+/// contains
+fn modify_entry_sizes(image: &mut [u8]) -> Result<()> {
+    for entry in read_entries(image)? {
+        image[entry.pos] = 1;
+        image[entry.pos + 1] = 1;
+        write_u32_le(image, entry.pos + 8, u32::max_value());
+    }
+    Ok(())
+}
+
+fn verify_offsets(image: &[u8]) -> Result<()> {
+    let entries = read_entries(image)?;
+    let first = entries.first().context("ICO directory is empty")?;
+    for entry in &entries {
+        assert_eq!(first.offset, entry.offset, "Entry offset is invalid");
+        let size = image.len() - first.offset as usize;
+        assert_eq!(size as u32, entry.size, "Entry size is invalid");
+    }
+    Ok(())
+}
+
+/// Every entry covers all embedded images, so they overlap each other
+fn modify_offsets(image: &mut [u8]) -> Result<()> {
+    let entries = read_entries(image)?;
+    let offset = entries.first().context("ICO directory is empty")?.offset;
+    let size = image.len() as u32 - offset;
+    for entry in entries {
+        write_u32_le(image, entry.pos + 8, size);
+        write_u32_le(image, entry.pos + 12, offset);
+    }
+    Ok(())
+}
+
+fn verify_entry_count(image: &[u8]) -> Result<()> {
+    let entries = read_entries(image)?;
+    assert_eq!(
+        HUGE_ENTRY_COUNT as usize,
+        entries.len(),
+        "Entry count is invalid"
+    );
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Describes existing images the way the parsers of the formats see them.
+//! Suspicious files from users or other tools show their header fields,
+//! their chunks or segments and anything which looks like an attack.
+
+use crate::{args::Inspect, bmp, gif, jpeg, png};
+#[cfg(unix)]
+use crate::{args::Verify, decoder};
+#[cfg(not(unix))]
+use common::bail;
+use common::{Context, Result};
+use std::fs;
+
+/// Number of chunks, segments, scans or frames beyond which decoders spend
+/// a lot of time on a single file
+pub(crate) const MAX_PLAUSIBLE_COUNT: usize = 1000;
+// About 1 GiB of RGBA pixels, more than decoders usually allow
+const MAX_PLAUSIBLE_PIXELS: u64 = 1 << 28;
+
+/// Chunk, segment or block of a file
+#[derive(Debug, Clone)]
+pub(crate) struct Part {
+    name: String,
+    pos: usize,
+    /// Size including headers and checksums
+    length: usize,
+}
+
+/// What the parser of a format found in a file
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    fields: Vec<(&'static str, String)>,
+    parts: Vec<Part>,
+    /// Width and height claimed by the header
+    pub(crate) claimed: Option<(u64, u64)>,
+    /// Number of pixels the image data holds at most
+    pub(crate) plausible: Option<u64>,
+    anomalies: Vec<String>,
+}
+
+impl Report {
+    pub(crate) fn field(&mut self, name: &'static str, value: impl ToString) {
+        self.fields.push((name, value.to_string()));
+    }
+
+    pub(crate) fn part(&mut self, name: impl Into<String>, pos: usize, length: usize) {
+        self.parts.push(Part {
+            name: name.into(),
+            pos,
+            length,
+        });
+    }
+
+    pub(crate) fn anomaly(&mut self, anomaly: impl Into<String>) {
+        self.anomalies.push(anomaly.into());
+    }
+
+    #[cfg(test)]
+    pub(crate) fn anomalies(&self) -> &[String] {
+        &self.anomalies
+    }
+}
+
+/// Prints a report for every file and decodes them afterwards if requested
+pub(crate) fn inspect(args: &Inspect) -> Result<()> {
+    for file in &args.files {
+        let image = fs::read(file).context("Unable to read image file")?;
+        let (format, inspect) = match detect(&image) {
+            Some(detected) => detected,
+            None => {
+                println!(
+                    "{}: Unknown format, only PNG, JPEG, GIF and BMP are supported",
+                    file.display()
+                );
+                continue;
+            }
+        };
+        let mut report = Report::default();
+        inspect(&image, &mut report);
+        check_dimensions(&mut report);
+        println!("{}: {}, {} bytes", file.display(), format, image.len());
+        print_report(&report);
+    }
+    if args.verify {
+        verify(args)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn verify(args: &Inspect) -> Result<()> {
+    let verify = Verify {
+        file: None,
+        memory_limit: args.memory_limit,
+        timeout: args.timeout,
+    };
+    decoder::verify(&args.files, &verify)
+}
+
+#[cfg(not(unix))]
+fn verify(_: &Inspect) -> Result<()> {
+    bail!(crate::DECODER_UNSUPPORTED)
+}
+
+/// Recognizes the format by its signature instead of the file extension
+fn detect(image: &[u8]) -> Option<(&'static str, fn(&[u8], &mut Report))> {
+    if image.starts_with(png::SIGNATURE) {
+        Some(("PNG", png::inspect))
+    } else if image.starts_with(&[0xff, jpeg::SOI]) {
+        Some(("JPEG", jpeg::inspect))
+    } else if image.starts_with(b"GIF8") {
+        Some(("GIF", gif::inspect))
+    } else if image.starts_with(b"BM") {
+        Some(("BMP", bmp::inspect))
+    } else {
+        None
+    }
+}
+
+/// Compares the claimed dimensions to the pixels the image data holds
+fn check_dimensions(report: &mut Report) {
+    let (width, height) = match report.claimed {
+        Some(claimed) => claimed,
+        None => return,
+    };
+    let pixels = width * height;
+    if pixels == 0 {
+        report.anomaly(format!(
+            "Dimensions of {}x{} contain no pixels",
+            width, height
+        ));
+    }
+    match report.plausible {
+        Some(plausible) if pixels > plausible => report.anomaly(format!(
+            "Dimensions of {}x{} claim {} pixels, but the image data holds at most {}",
+            width, height, pixels, plausible
+        )),
+        _ if pixels > MAX_PLAUSIBLE_PIXELS => report.anomaly(format!(
+            "Dimensions of {}x{} claim {} pixels, which takes more memory than decoders usually allow",
+            width, height, pixels
+        )),
+        _ => {}
+    }
+}
+
+fn print_report(report: &Report) {
+    println!("  Header:");
+    for (name, value) in &report.fields {
+        println!("    {}: {}", name, value);
+    }
+    println!("  Structure:");
+    // runs of the same part, like thousands of text chunks, take a single line
+    let mut index = 0;
+    while index < report.parts.len() {
+        let part = &report.parts[index];
+        let run = report.parts[index..]
+            .iter()
+            .take_while(|other| other.name == part.name)
+            .count();
+        let length: usize = report.parts[index..index + run]
+            .iter()
+            .map(|part| part.length)
+            .sum();
+        if run == 1 {
+            println!("    {} at {}, {} bytes", part.name, part.pos, length);
+        } else {
+            println!(
+                "    {} {} times from {}, {} bytes",
+                part.name, run, part.pos, length
+            );
+        }
+        index += run;
+    }
+    if let Some((width, height)) = report.claimed {
+        match report.plausible {
+            Some(plausible) => println!(
+                "  Dimensions: {}x{} claimed, at most {} pixels plausible",
+                width, height, plausible
+            ),
+            None => println!("  Dimensions: {}x{} claimed", width, height),
+        }
+    }
+    if report.anomalies.is_empty() {
+        println!("  No anomalies");
+    } else {
+        println!("  Anomalies:");
+        for anomaly in &report.anomalies {
+            println!("    {}", anomaly);
+        }
+    }
+}
@@ -1,18 +1,155 @@
-use crate::{extract_u16, write_u16, HEIGHT, NEW_HEIGHT, NEW_WIDTH, WIDTH};
+use crate::{
+    args::{Args, Mode},
+    extract_u16,
+    fuzz::{self, Field, Structure},
+    inspect::{Report, MAX_PLAUSIBLE_COUNT},
+    metadata,
+    polyglot::{self, Format},
+    progressive::{self, Scan},
+    write_u16,
+};
 use common::{bail, Context, Result};
 use image::{jpeg::JPEGEncoder, EncodableLayout, ImageBuffer, Pixel, Rgb};
-use std::fs;
+use std::{convert::TryFrom, fs};
 
-pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<()> {
-    let mut image = create_jpeg(image)?;
-    verify_image(&image, WIDTH, HEIGHT)?;
-    modify_width_and_height(&mut image, NEW_WIDTH, NEW_HEIGHT)?;
-    verify_image(&image, NEW_WIDTH, NEW_HEIGHT)?;
-    fs::write("output.jpeg", image).context("Unable to write to image file")?;
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::ScanFlood,
+    Mode::HuffmanTable,
+    Mode::RestartInterval,
+    Mode::ComponentCount,
+    Mode::ExifCyclicIfd,
+    Mode::ExifTagCount,
+    Mode::OversizedXmp,
+    Mode::ThumbnailDimensions,
+    Mode::Polyglot,
+    Mode::Fuzz,
+];
+
+const SOF0: u8 = 0xc0;
+pub(crate) const DHT: u8 = 0xc4;
+const JPG: u8 = 0xc8;
+const DAC: u8 = 0xcc;
+const RST0: u8 = 0xd0;
+const RST7: u8 = 0xd7;
+pub(crate) const SOI: u8 = 0xd8;
+pub(crate) const EOI: u8 = 0xd9;
+pub(crate) const SOS: u8 = 0xda;
+pub(crate) const DQT: u8 = 0xdb;
+const DNL: u8 = 0xdc;
+const DRI: u8 = 0xdd;
+pub(crate) const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP15: u8 = 0xef;
+const COM: u8 = 0xfe;
+const TEM: u8 = 0x01;
+
+// Every AC scan is sent this often, which results in 1135 scans
+const SCAN_REPEATS: usize = 6;
+// Code lengths of 1 to 16 bits with 255 codes each
+const HUFFMAN_CODE_COUNT: usize = 16 * 255;
+const HUGE_RESTART_INTERVAL: u16 = u16::max_value();
+const MAX_COMPONENT_COUNT: usize = 255;
+const MAX_PAYLOAD_SIZE: usize = u16::max_value() as usize - 2;
+// Tags the entropy-coded data following SOS and RST markers, which is no
+// segment of its own
+const ENTROPY_CODED_DATA: u32 = 0x100;
+// Blocks take at least 1 bit codes for a DC difference of 0 and the end of
+// block. Progressive scans code whole bands of blocks using a single end of
+// band run, so only the DC coefficient remains.
+const MIN_BLOCK_BITS: u64 = 2;
+const MIN_PROGRESSIVE_BLOCK_BITS: u64 = 1;
+const BLOCK_PIXELS: u64 = 64;
+const MAX_IDENTIFIER_SIZE: usize = 32;
+
+/// Segment of a JPEG file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Segment {
+    /// Position of the marker after any fill bytes
+    pub(crate) pos: usize,
+    pub(crate) marker: u8,
+    /// Length of the segment without the marker, 0 for standalone markers
+    pub(crate) length: usize,
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let scans = if args.mode == Mode::ScanFlood {
+        Some(scan_flood())
+    } else if args.progressive {
+        Some(progressive::default_scans())
+    } else {
+        None
+    };
+    let mut jpeg = if let Some(scans) = scans {
+        let jpeg = progressive::encode(image, &scans)?;
+        verify_progressive(&jpeg, scans.len())?;
+        jpeg
+    } else {
+        create_jpeg(image)?
+    };
+    verify_image(&jpeg, args.width, args.height)?;
+    match args.mode {
+        Mode::Dimensions => {
+            let (new_width, new_height) = args.new_dimensions_u16()?;
+            modify_width_and_height(&mut jpeg, new_width, new_height)?;
+            verify_image(&jpeg, new_width, new_height)?;
+        }
+        Mode::ScanFlood => {}
+        Mode::HuffmanTable => {
+            let mut table = Vec::new();
+            write_segment(&mut table, DHT, &huffman_table())?;
+            jpeg = insert_segments(&jpeg, is_scan, &table)?;
+            verify_huffman_table(&jpeg)?;
+        }
+        Mode::RestartInterval => {
+            let mut restart = Vec::new();
+            write_segment(&mut restart, DRI, &HUGE_RESTART_INTERVAL.to_be_bytes())?;
+            jpeg = insert_segments(&jpeg, is_scan, &restart)?;
+            verify_restart_interval(&jpeg)?;
+        }
+        Mode::ComponentCount => {
+            jpeg = modify_component_count(&jpeg)?;
+            verify_component_count(&jpeg)?;
+        }
+        Mode::ExifCyclicIfd | Mode::ExifTagCount | Mode::ThumbnailDimensions => {
+            let mut exif = metadata::EXIF_HEADER.to_vec();
+            exif.extend(metadata::create_exif(image, args.mode)?);
+            let mut segment = Vec::new();
+            write_segment(&mut segment, APP1, &exif)?;
+            jpeg = insert_segments(&jpeg, is_after_jfif, &segment)?;
+            verify_image(&jpeg, args.width, args.height)?;
+            let exif = app1_data(&jpeg, metadata::EXIF_HEADER)?;
+            metadata::verify_exif(exif.context("Unable to find APP1 EXIF")?, args.mode)?;
+        }
+        Mode::OversizedXmp => {
+            let mut xmp = metadata::XMP_NAMESPACE.to_vec();
+            xmp.extend(metadata::xmp_packet());
+            let mut segments = Vec::new();
+            write_segment(&mut segments, APP1, &xmp)?;
+            write_segment(&mut segments, APP1, &metadata::extended_xmp())?;
+            jpeg = insert_segments(&jpeg, is_after_jfif, &segments)?;
+            verify_image(&jpeg, args.width, args.height)?;
+            let xmp = app1_data(&jpeg, metadata::XMP_NAMESPACE)?;
+            let xmp = xmp.context("Unable to find APP1 XMP")?;
+            assert_eq!(metadata::xmp_packet(), xmp, "XMP packet is invalid");
+            let extended = app1_data(&jpeg, metadata::EXTENDED_XMP_NAMESPACE)?;
+            metadata::verify_extended_xmp(extended.context("Unable to find APP1 extended XMP")?);
+        }
+        Mode::Polyglot => {
+            let mut comment = Vec::new();
+            write_segment(&mut comment, COM, polyglot::HTML)?;
+            jpeg = insert_segments(&jpeg, is_after_jfif, &comment)?;
+            verify_image(&jpeg, args.width, args.height)?;
+            polyglot::verify_formats(&jpeg, &[Format::Jpeg, Format::Html])?;
+        }
+        Mode::Fuzz => return fuzz::write_corpus(&jpeg, &structure(&jpeg)?, args),
+        _ => bail!("Mode is not supported by JPEG"),
+    }
+    fs::write(args.output_path()?, jpeg).context("Unable to write to image file")?;
     Ok(())
 }
 
-fn create_jpeg(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+pub(crate) fn create_jpeg(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     let mut output: Vec<u8> = Vec::new();
     let mut encoder = JPEGEncoder::new(&mut output);
     encoder
@@ -26,34 +163,610 @@ fn create_jpeg(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-fn verify_image(image: &[u8], width: u16, height: u16) -> Result<()> {
-    assert_eq!([0xff, 0xd8], &image[0..2], "JPEG SOI is not valid");
-    let pos = find_sof0(image)?;
-    let img_width = extract_u16(image, pos + 5);
-    let img_height = extract_u16(image, pos + 7);
-    assert_eq!(width, img_width, "Image width is invalid");
-    assert_eq!(height, img_height, "Image height is invalid");
+pub(crate) fn write_segment(output: &mut Vec<u8>, marker: u8, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        bail!(
+            "Payload of {} bytes does not fit into a JPEG segment",
+            payload.len()
+        );
+    }
+    let size = u16::try_from(payload.len() + 2).context("Segment size is invalid")?;
+    output.extend_from_slice(&[0xff, marker]);
+    output.extend_from_slice(&size.to_be_bytes());
+    output.extend_from_slice(payload);
     Ok(())
 }
 
-fn find_sof0(image: &[u8]) -> Result<usize> {
-    let mut pos = 2;
+/// Walks all segments including the restart markers within scans until EOI
+pub(crate) fn parse(image: &[u8]) -> Result<Vec<Segment>> {
+    if image.get(0..2) != Some(&[0xff, SOI]) {
+        bail!("JPEG SOI is not valid");
+    }
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    loop {
+        if image.get(pos) != Some(&0xff) {
+            bail!("Expected marker at {}", pos);
+        }
+        // any number of fill bytes may precede a marker
+        while image.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *image.get(pos + 1).context("Marker exceeds the file")?;
+        let length = if is_standalone(marker) {
+            0
+        } else if pos + 4 > image.len() {
+            bail!("Segment length exceeds the file");
+        } else {
+            extract_u16(image, pos + 2) as usize
+        };
+        if pos + 2 + length > image.len() {
+            bail!("Segment exceeds the file");
+        }
+        segments.push(Segment {
+            pos,
+            marker,
+            length,
+        });
+        pos += 2 + length;
+        match marker {
+            EOI => return Ok(segments),
+            SOS | RST0..=RST7 => pos = skip_entropy_coded_data(image, pos)?,
+            _ => {}
+        }
+    }
+}
+
+/// Every segment following SOI and every entropy-coded data is a field. The
+/// segment lengths are fixed up.
+fn structure(image: &[u8]) -> Result<Structure> {
+    let segments = parse(image)?;
+    let mut fields = Vec::new();
+    for (index, segment) in segments.iter().enumerate().skip(1) {
+        let end = segment.pos + 2 + segment.length;
+        let payload = image.get(segment.pos + 4..end).unwrap_or(&[]);
+        fields.push(Field {
+            tag: segment.marker.into(),
+            data: payload.to_vec(),
+        });
+        match segments.get(index + 1) {
+            Some(next) if next.pos > end => fields.push(Field {
+                tag: ENTROPY_CODED_DATA,
+                data: image[end..next.pos].to_vec(),
+            }),
+            _ => {}
+        }
+    }
+    Ok(Structure {
+        fields,
+        fixed: 0,
+        big_endian: true,
+        assemble: assemble_segments,
+    })
+}
+
+fn assemble_segments(fields: &[Field]) -> Result<Vec<u8>> {
+    let mut output = vec![0xff, SOI];
+    for field in fields {
+        match u8::try_from(field.tag) {
+            Ok(marker) if is_standalone(marker) => output.extend_from_slice(&[0xff, marker]),
+            Ok(marker) => {
+                let size = field.data.len().min(MAX_PAYLOAD_SIZE);
+                write_segment(&mut output, marker, &field.data[..size])?;
+            }
+            Err(_) => output.extend_from_slice(&field.data),
+        }
+    }
+    Ok(output)
+}
+
+fn is_standalone(marker: u8) -> bool {
+    marker == SOI || marker == EOI || marker == TEM || (RST0..=RST7).contains(&marker)
+}
+
+/// Start of Frame markers of all coding processes
+pub(crate) fn is_frame(marker: u8) -> bool {
+    (SOF0..=SOF0 + 15).contains(&marker) && marker != DHT && marker != JPG && marker != DAC
+}
+
+/// Returns the position of the next marker. A 0xff within the data is
+/// followed by a stuffed 0.
+fn skip_entropy_coded_data(image: &[u8], pos: usize) -> Result<usize> {
+    let mut pos = pos;
     loop {
-        if pos + 4 > image.len() {
-            bail!("Unable to find SOF0 Frame");
+        match image.get(pos..pos + 2) {
+            Some(&[0xff, 0]) => pos += 2,
+            Some(&[0xff, _]) => return Ok(pos),
+            Some(_) => pos += 1,
+            None => bail!("Unable to find the end of the scan"),
         }
-        if image[pos..pos + 2] == [0xff, 0xc0] {
-            break;
+    }
+}
+
+fn frames(image: &[u8]) -> Result<Vec<Segment>> {
+    let frames: Vec<Segment> = parse(image)?
+        .into_iter()
+        .filter(|segment| is_frame(segment.marker))
+        .collect();
+    if frames.is_empty() {
+        bail!("Unable to find SOF Frame");
+    }
+    Ok(frames)
+}
+
+pub(crate) fn verify_image(image: &[u8], width: u16, height: u16) -> Result<()> {
+    assert_eq!([0xff, SOI], &image[0..2], "JPEG SOI is not valid");
+    for frame in frames(image)? {
+        let img_height = extract_u16(image, frame.pos + 5);
+        let img_width = extract_u16(image, frame.pos + 7);
+        assert_eq!(width, img_width, "Image width is invalid");
+        assert_eq!(height, img_height, "Image height is invalid");
+    }
+    Ok(())
+}
+
+fn verify_progressive(image: &[u8], scans: usize) -> Result<()> {
+    let segments = parse(image)?;
+    for frame in segments.iter().filter(|segment| is_frame(segment.marker)) {
+        assert_eq!(progressive::SOF2, frame.marker, "Frame is not progressive");
+    }
+    let found = segments
+        .iter()
+        .filter(|segment| segment.marker == SOS)
+        .count();
+    assert_eq!(scans, found, "Scan count is invalid");
+    Ok(())
+}
+
+pub(crate) fn modify_width_and_height(
+    image: &mut [u8],
+    new_width: u16,
+    new_height: u16,
+) -> Result<()> {
+    for frame in frames(image)? {
+        write_u16(image, frame.pos + 5, new_height);
+        write_u16(image, frame.pos + 7, new_width);
+    }
+    Ok(())
+}
+
+/// DC scan followed by scans of single AC coefficients, which are repeated
+/// although every band may only be sent once
+fn scan_flood() -> Vec<Scan> {
+    let mut scans = vec![Scan {
+        components: (0..progressive::COMPONENTS).collect(),
+        start: 0,
+        end: 0,
+    }];
+    for _ in 0..SCAN_REPEATS {
+        for coefficient in 1..64 {
+            for component in 0..progressive::COMPONENTS {
+                scans.push(Scan {
+                    components: vec![component],
+                    start: coefficient,
+                    end: coefficient,
+                });
+            }
         }
-        pos += 2;
-        pos += extract_u16(image, pos) as usize;
     }
-    Ok(pos)
+    scans
+}
+
+/// Inserts the segments in front of the first segment whose marker matches
+fn insert_segments(image: &[u8], before: fn(u8) -> bool, segments: &[u8]) -> Result<Vec<u8>> {
+    let pos = parse(image)?
+        .into_iter()
+        .find(|segment| before(segment.marker))
+        .context("Unable to find where to insert the segments")?
+        .pos;
+    let mut output = image[..pos].to_vec();
+    output.extend_from_slice(segments);
+    output.extend_from_slice(&image[pos..]);
+    Ok(output)
+}
+
+/// Tables in front of the first scan apply to all scans
+fn is_scan(marker: u8) -> bool {
+    marker == SOS
+}
+
+/// APP1 metadata directly follows the JFIF APP0 segment
+fn is_after_jfif(marker: u8) -> bool {
+    marker != SOI && marker != APP0
+}
+
+/// Data of the first APP1 segment which starts with the header, without
+/// the header
+fn app1_data<'a>(image: &'a [u8], header: &[u8]) -> Result<Option<&'a [u8]>> {
+    Ok(parse(image)?
+        .into_iter()
+        .filter(|segment| segment.marker == APP1)
+        .map(|segment| &image[segment.pos + 4..segment.pos + 2 + segment.length])
+        .find(|data| data.starts_with(header))
+        .map(|data| &data[header.len()..]))
+}
+
+/// Replaces the first luma AC table with one whose code lengths claim 255
+/// codes each. Neither fit so many codes into 16 bit, nor are there enough
+/// symbols.
+fn huffman_table() -> Vec<u8> {
+    let mut table = vec![0x10];
+    table.extend_from_slice(&[255; 16]);
+    table.extend((0..HUFFMAN_CODE_COUNT).map(|value| value as u8));
+    table
+}
+
+fn verify_huffman_table(image: &[u8]) -> Result<()> {
+    let segments = parse(image)?;
+    let table = segments
+        .iter()
+        .take_while(|segment| segment.marker != SOS)
+        .filter(|segment| segment.marker == DHT)
+        .last()
+        .context("Unable to find DHT Huffman table")?;
+    assert_eq!(
+        2 + 1 + 16 + HUFFMAN_CODE_COUNT,
+        table.length,
+        "DHT length is invalid"
+    );
+    assert_eq!(0x10, image[table.pos + 4], "DHT class is invalid");
+    let count: usize = image[table.pos + 5..table.pos + 21]
+        .iter()
+        .map(|&count| count as usize)
+        .sum();
+    assert_eq!(HUFFMAN_CODE_COUNT, count, "DHT code count is invalid");
+    Ok(())
+}
+
+fn verify_restart_interval(image: &[u8]) -> Result<()> {
+    let segments = parse(image)?;
+    let restart = segments
+        .iter()
+        .find(|segment| segment.marker == DRI)
+        .context("Unable to find DRI restart interval")?;
+    assert_eq!(
+        HUGE_RESTART_INTERVAL,
+        extract_u16(image, restart.pos + 4),
+        "Restart interval is invalid"
+    );
+    assert!(
+        !segments
+            .iter()
+            .any(|segment| (RST0..=RST7).contains(&segment.marker)),
+        "Image contains restart markers"
+    );
+    Ok(())
+}
+
+/// Adds components to every frame until it claims 255 of them, while the
+/// scans still only contain the original ones
+fn modify_component_count(image: &[u8]) -> Result<Vec<u8>> {
+    let mut image = image.to_vec();
+    for frame in frames(&image)?.into_iter().rev() {
+        let count = image[frame.pos + 9] as usize;
+        let end = frame.pos + 10 + count * 3;
+        let used: Vec<u8> = image[frame.pos + 10..end]
+            .iter()
+            .step_by(3)
+            .copied()
+            .collect();
+        let components: Vec<u8> = (0..=255)
+            .filter(|id| !used.contains(id))
+            .take(MAX_COMPONENT_COUNT - count)
+            .flat_map(|id| vec![id, 0x11, 0])
+            .collect();
+        image = [&image[..end], &components, &image[end..]].concat();
+        image[frame.pos + 9] = MAX_COMPONENT_COUNT as u8;
+        write_u16(
+            &mut image,
+            frame.pos + 2,
+            (8 + MAX_COMPONENT_COUNT * 3) as u16,
+        );
+    }
+    Ok(image)
 }
 
-fn modify_width_and_height(image: &mut [u8], new_width: u16, new_height: u16) -> Result<()> {
-    let pos = find_sof0(image)?;
-    write_u16(image, pos + 5, new_width);
-    write_u16(image, pos + 7, new_height);
+fn verify_component_count(image: &[u8]) -> Result<()> {
+    let segments = parse(image)?;
+    for segment in &segments {
+        if is_frame(segment.marker) {
+            assert_eq!(
+                MAX_COMPONENT_COUNT,
+                image[segment.pos + 9] as usize,
+                "Frame component count is invalid"
+            );
+            assert_eq!(
+                8 + MAX_COMPONENT_COUNT * 3,
+                segment.length,
+                "Frame length is invalid"
+            );
+        } else if segment.marker == SOS {
+            assert!(
+                (image[segment.pos + 4] as usize) < MAX_COMPONENT_COUNT,
+                "Scan component count is invalid"
+            );
+        }
+    }
     Ok(())
 }
+
+/// Describes the frame, the segments and the entropy-coded data
+pub(crate) fn inspect(image: &[u8], report: &mut Report) {
+    let segments = match parse(image) {
+        Ok(segments) => segments,
+        Err(error) => {
+            report.anomaly(format!("{:#}", error));
+            return;
+        }
+    };
+    let mut entropy_coded = 0;
+    let mut restart_markers = 0;
+    let mut scans = 0;
+    for (index, segment) in segments.iter().enumerate() {
+        let end = segment.pos + 2 + segment.length;
+        if let Some(next) = segments.get(index + 1) {
+            entropy_coded += next.pos - end;
+        }
+        match segment.marker {
+            // restart markers only separate the entropy-coded data
+            RST0..=RST7 => {
+                restart_markers += 1;
+                continue;
+            }
+            SOS => scans += 1,
+            _ => {}
+        }
+        let name = segment_name(image, *segment);
+        if !is_standalone(segment.marker) && segment.length < 2 {
+            report.anomaly(format!(
+                "{} at {} has a length of {}",
+                name, segment.pos, segment.length
+            ));
+        }
+        report.part(name, segment.pos, segment.length + 2);
+        if segment.marker == APP1 && segment.length >= 2 {
+            let data = &image[segment.pos + 4..end];
+            if data.starts_with(metadata::EXIF_HEADER) {
+                metadata::inspect_exif(&data[metadata::EXIF_HEADER.len()..], report);
+            } else if data.starts_with(metadata::EXTENDED_XMP_NAMESPACE) {
+                let xmp = &data[metadata::EXTENDED_XMP_NAMESPACE.len()..];
+                metadata::inspect_extended_xmp(xmp, image.len(), report);
+            }
+        } else if segment.marker == DHT {
+            inspect_huffman_tables(image, *segment, report);
+        }
+    }
+    if let Some(eoi) = segments.last() {
+        let end = eoi.pos + 2;
+        if end < image.len() {
+            report.anomaly(format!("{} bytes follow EOI", image.len() - end));
+        }
+    }
+
+    let frames: Vec<&Segment> = segments
+        .iter()
+        .filter(|segment| is_frame(segment.marker))
+        .collect();
+    match frames.first() {
+        Some(frame) if frame.length >= 8 => {
+            let pos = frame.pos;
+            let (height, width) = (extract_u16(image, pos + 5), extract_u16(image, pos + 7));
+            let components = image[pos + 9] as usize;
+            report.field("Process", frame_process(frame.marker));
+            report.field("Precision", image[pos + 4]);
+            report.field("Width", width);
+            report.field("Height", height);
+            report.field("Components", components);
+            report.claimed = Some((width.into(), height.into()));
+            if height == 0 && !segments.iter().any(|segment| segment.marker == DNL) {
+                report.anomaly("Height of 0 is not defined by a DNL segment");
+            }
+            if ![1, 3, 4].contains(&components) {
+                report.anomaly(format!("Frame has {} components", components));
+            }
+            if frame.length != 8 + components * 3 {
+                report.anomaly(format!(
+                    "Frame length of {} does not match {} components",
+                    frame.length, components
+                ));
+            }
+            let bits = entropy_coded as u64 * 8;
+            // lossless coding takes at least 1 bit per pixel
+            report.plausible = Some(match frame.marker & 3 {
+                2 => bits / MIN_PROGRESSIVE_BLOCK_BITS * BLOCK_PIXELS,
+                3 => bits,
+                _ => bits / MIN_BLOCK_BITS * BLOCK_PIXELS,
+            });
+        }
+        Some(frame) => report.anomaly(format!("Frame at {} is too short", frame.pos)),
+        None => report.anomaly("SOF Frame is missing"),
+    }
+    if frames.len() > 1 {
+        report.anomaly(format!("File contains {} frames", frames.len()));
+    }
+
+    report.field("Scans", scans);
+    report.field("Restart markers", restart_markers);
+    report.field("Entropy-coded data", entropy_coded);
+    if scans == 0 {
+        report.anomaly("SOS is missing");
+    } else if scans > MAX_PLAUSIBLE_COUNT {
+        report.anomaly(format!("File contains {} scans", scans));
+    }
+    if let Some(restart) = segments
+        .iter()
+        .find(|segment| segment.marker == DRI && segment.length >= 4)
+    {
+        let interval = extract_u16(image, restart.pos + 4);
+        report.field("Restart interval", interval);
+        if interval > 0 && restart_markers == 0 {
+            report.anomaly(format!(
+                "Restart interval of {} without any restart markers",
+                interval
+            ));
+        }
+    }
+}
+
+/// A table may neither contain more than 256 symbols nor more codes than
+/// fit into their lengths
+fn inspect_huffman_tables(image: &[u8], segment: Segment, report: &mut Report) {
+    let end = segment.pos + 2 + segment.length;
+    let mut pos = segment.pos + 4;
+    while pos + 17 <= end {
+        let counts = &image[pos + 1..pos + 17];
+        let symbols: usize = counts.iter().map(|&count| count as usize).sum();
+        // every length doubles the codes available, the used ones are gone
+        let mut available = 1_u64;
+        let mut oversubscribed = false;
+        for &count in counts {
+            available *= 2;
+            if count as u64 > available {
+                oversubscribed = true;
+                break;
+            }
+            available -= count as u64;
+        }
+        if symbols > 256 || oversubscribed {
+            report.anomaly(format!(
+                "Huffman table {:#04x} at {} has {} codes, which do not fit",
+                image[pos], segment.pos, symbols
+            ));
+        }
+        pos += 17 + symbols;
+    }
+}
+
+/// Name of the marker, application segments include their identifier like
+/// JFIF or Exif
+fn segment_name(image: &[u8], segment: Segment) -> String {
+    match segment.marker {
+        SOI => "SOI".into(),
+        EOI => "EOI".into(),
+        SOS => "SOS".into(),
+        DQT => "DQT".into(),
+        DHT => "DHT".into(),
+        DAC => "DAC".into(),
+        DNL => "DNL".into(),
+        DRI => "DRI".into(),
+        COM => "COM".into(),
+        TEM => "TEM".into(),
+        APP0..=APP15 => {
+            let payload = image
+                .get(segment.pos + 4..segment.pos + 2 + segment.length)
+                .unwrap_or(&[]);
+            let identifier: String = payload
+                .iter()
+                .take_while(|byte| byte.is_ascii_graphic())
+                .take(MAX_IDENTIFIER_SIZE)
+                .map(|&byte| byte as char)
+                .collect();
+            format!("APP{} {}", segment.marker - APP0, identifier)
+                .trim_end()
+                .into()
+        }
+        marker if is_frame(marker) => format!("SOF{}", marker - SOF0),
+        marker => format!("{:#04x}", marker),
+    }
+}
+
+fn frame_process(marker: u8) -> &'static str {
+    match marker & 3 {
+        0 => "baseline",
+        1 => "extended sequential",
+        2 => "progressive",
+        _ => "lossless",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_jpeg, is_frame, parse, DQT, EOI, RST0, SOI, SOS};
+    use crate::image::generate_image;
+
+    fn create() -> Vec<u8> {
+        create_jpeg(&generate_image(8, 8, 1)).expect("Unable to create JPEG")
+    }
+
+    #[test]
+    fn parse_created_jpeg() {
+        let image = create();
+        let segments = parse(&image).expect("Unable to parse JPEG");
+        let first = segments.first().expect("SOI is missing");
+        let last = segments.last().expect("EOI is missing");
+        assert_eq!((0, SOI), (first.pos, first.marker), "SOI is invalid");
+        assert_eq!(
+            (image.len() - 2, EOI),
+            (last.pos, last.marker),
+            "EOI is invalid"
+        );
+        assert!(
+            segments.iter().any(|segment| is_frame(segment.marker)),
+            "Frame is missing"
+        );
+        assert!(
+            segments.iter().any(|segment| segment.marker == SOS),
+            "Scan is missing"
+        );
+        for pair in segments.windows(2) {
+            assert!(
+                pair[0].pos + 2 + pair[0].length <= pair[1].pos,
+                "Segments overlap"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_fill_bytes_and_restart_markers() {
+        let image = [
+            &[0xff, SOI][..],
+            // fill bytes in front of DQT
+            &[0xff, 0xff, 0xff, DQT, 0, 4, 0xaa, 0xbb],
+            &[0xff, SOS, 0, 3, 0xcc],
+            // entropy-coded data with a stuffed 0xff
+            &[0x12, 0xff, 0, 0x34],
+            &[0xff, RST0, 0x56],
+            // fill byte in front of RST1
+            &[0xff, 0xff, RST0 + 1, 0x78],
+            &[0xff, EOI],
+        ]
+        .concat();
+        let segments: Vec<_> = parse(&image)
+            .expect("Unable to parse JPEG")
+            .iter()
+            .map(|segment| (segment.pos, segment.marker, segment.length))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, SOI, 0),
+                (4, DQT, 4),
+                (10, SOS, 3),
+                (19, RST0, 0),
+                (23, RST0 + 1, 0),
+                (26, EOI, 0),
+            ],
+            segments,
+            "Segments are invalid"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_truncated_jpeg() {
+        let image = create();
+        for length in 0..image.len() {
+            assert!(
+                parse(&image[..length]).is_err(),
+                "JPEG truncated to {} bytes parses",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_missing_marker() {
+        let mut image = create();
+        let segments = parse(&image).expect("Unable to parse JPEG");
+        image[segments[1].pos] = 0;
+        assert!(parse(&image).is_err(), "Missing marker parses");
+    }
+}
@@ -61,22 +61,37 @@
 )]
 
 mod args;
+mod batch;
+mod bits;
+mod bmp;
+#[cfg(unix)]
+mod decoder;
+mod fuzz;
 mod gif;
+mod ico;
 mod image;
+mod inspect;
 mod jpeg;
+mod metadata;
 mod png;
-mod bmp;
-
-use args::{Args, ImageFormat};
+mod polyglot;
+mod progressive;
+mod tiff;
+mod upload;
+mod vp8l;
+mod webp;
+mod zlib;
+
+use ::image::{ImageBuffer, Rgb};
+use args::{Args, Command, ImageFormat, Mode};
 use clap::derive::Clap;
-use common::Result;
-
-pub(crate) const WIDTH: u16 = 512;
-pub(crate) const HEIGHT: u16 = 512;
+use common::{bail, Result};
+use std::path::PathBuf;
 
-// Takes about 12GB of RAM to load
-pub(crate) const NEW_WIDTH: u16 = 65_500;
-pub(crate) const NEW_HEIGHT: u16 = 65_500;
+/// The decoder is limited using rlimits and wait4
+#[cfg(not(unix))]
+pub(crate) const DECODER_UNSUPPORTED: &str =
+    "Decoding in a limited child process is only supported on Unix";
 
 pub(crate) fn extract_u32(data: &[u8], start: usize) -> u32 {
     let mut buf = [0_u8; 4];
@@ -126,35 +141,91 @@ pub(crate) fn write_u16_le(data: &mut [u8], start: usize, val: u16) {
     buf.copy_from_slice(&bytes);
 }
 
+pub(crate) fn modes(format: &ImageFormat) -> &'static [Mode] {
+    match *format {
+        ImageFormat::JPEG => jpeg::MODES,
+        ImageFormat::PNG => png::MODES,
+        ImageFormat::GIF => gif::MODES,
+        ImageFormat::BMP => bmp::MODES,
+        ImageFormat::TIFF => tiff::MODES,
+        ImageFormat::WEBP => webp::MODES,
+        ImageFormat::ICO | ImageFormat::CUR => ico::MODES,
+    }
+}
+
 fn main() -> Result<()> {
-    let args: Args = Args::parse();
-    let image = image::generate_image();
-    match args.format {
-        ImageFormat::JPEG => jpeg::create_image(&image),
-        ImageFormat::PNG => png::create_image(&image),
-        ImageFormat::GIF => gif::create_image(&image),
-        ImageFormat::BMP => bmp::create_image(&image),
+    let mut args: Args = Args::parse();
+    // generators and decoders assume at least a single pixel
+    if args.width == 0 || args.height == 0 {
+        bail!("width and height must be at least 1");
+    }
+    match &args.command {
+        #[cfg(unix)]
+        Some(Command::Decode(decode)) => return decoder::decode(&decode.file),
+        #[cfg(unix)]
+        Some(Command::Verify(verify)) => {
+            if let Some(file) = &verify.file {
+                return decoder::verify(&[file.clone()], verify);
+            }
+        }
+        #[cfg(not(unix))]
+        Some(Command::Decode(_)) | Some(Command::Verify(_)) => bail!(DECODER_UNSUPPORTED),
+        Some(Command::Upload(upload)) => {
+            if let Some(file) = &upload.file {
+                return upload::upload(&[file.clone()], upload);
+            }
+        }
+        Some(Command::All(all)) => {
+            let directory = all.directory.clone();
+            let image = generate(&mut args);
+            return batch::create_all(&image, &args, &directory);
+        }
+        Some(Command::Inspect(inspect)) => return inspect::inspect(inspect),
+        None => {}
+    }
+    let format = args.format()?;
+    if !modes(format).contains(&args.mode) {
+        bail!(
+            "The selected mode is not supported by {}",
+            format.extension()
+        );
+    }
+    let image = generate(&mut args);
+    create_image(&image, &args)?;
+    match &args.command {
+        #[cfg(unix)]
+        Some(Command::Verify(verify)) => decoder::verify(&generated_files(&args)?, verify),
+        Some(Command::Upload(upload)) => upload::upload(&generated_files(&args)?, upload),
+        _ => Ok(()),
     }
 }
 
-#[allow(clippy::use_debug, dead_code)]
-fn print_dimension(args: &Args) {
-    let d = match args.format {
-        ImageFormat::JPEG => ::image::image_dimensions("./output.jpeg"),
-        ImageFormat::PNG => ::image::image_dimensions("./output.png"),
-        ImageFormat::GIF => ::image::image_dimensions("./output.gif"),
-        ImageFormat::BMP => ::image::image_dimensions("./output.bmp"),
-    };
-    println!("{:?}", d);
+/// Generates the noise and remembers the used seed
+fn generate(args: &mut Args) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let seed = args.seed.unwrap_or_else(image::random_seed);
+    println!("Using seed {}", seed);
+    // fuzzing derives its mutations from the same seed
+    args.seed = Some(seed);
+    image::generate_image(args.width, args.height, seed)
+}
+
+fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    match args.format()? {
+        ImageFormat::JPEG => jpeg::create_image(image, args),
+        ImageFormat::PNG => png::create_image(image, args),
+        ImageFormat::GIF => gif::create_image(image, args),
+        ImageFormat::BMP => bmp::create_image(image, args),
+        ImageFormat::TIFF => tiff::create_image(image, args),
+        ImageFormat::WEBP => webp::create_image(image, args),
+        ImageFormat::ICO | ImageFormat::CUR => ico::create_image(image, args),
+    }
 }
 
-#[allow(clippy::use_debug, dead_code, clippy::result_expect_used)]
-fn open(args: &Args) {
-    let _ = match args.format {
-        ImageFormat::JPEG => ::image::open("./output.jpeg"),
-        ImageFormat::PNG => ::image::open("./output.png"),
-        ImageFormat::GIF => ::image::open("./output.gif"),
-        ImageFormat::BMP => ::image::open("./output.bmp"),
+/// Files written by the selected mode
+fn generated_files(args: &Args) -> Result<Vec<PathBuf>> {
+    if args.mode == Mode::Fuzz {
+        fuzz::corpus_files(args)
+    } else {
+        Ok(vec![args.output_path()?])
     }
-    .expect("Unable to open file");
 }
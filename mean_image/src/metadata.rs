@@ -0,0 +1,416 @@
+//! EXIF and XMP metadata, which JPEG and PNG files embed. EXIF is a TIFF
+//! structure of its own, so it is written in big endian independent of the
+//! surrounding file.
+
+use crate::{
+    args::Mode, extract_u16, extract_u16_le, extract_u32, extract_u32_le, inspect::Report, jpeg,
+};
+use common::{bail, Context, Result};
+use image::{imageops, ImageBuffer, Rgb};
+
+/// Precedes the TIFF structure within the JPEG APP1 segment
+pub(crate) const EXIF_HEADER: &[u8] = b"Exif\0\0";
+pub(crate) const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+pub(crate) const EXTENDED_XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+/// Keyword of the PNG iTXt chunk containing XMP
+pub(crate) const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+const TIFF_HEADER: &[u8] = b"MM\0\x2a\0\0\0\x08";
+const LITTLE_ENDIAN: &[u8] = b"II";
+const IFD0: u32 = 8;
+const ENTRY_SIZE: u32 = 12;
+
+const IMAGE_WIDTH: u16 = 0x0100;
+const IMAGE_LENGTH: u16 = 0x0101;
+const COMPRESSION: u16 = 0x0103;
+const IMAGE_DESCRIPTION: u16 = 0x010e;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const EXIF_VERSION: u16 = 0x9000;
+
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const UNDEFINED: u16 = 7;
+// Sizes of the types BYTE to DOUBLE
+const TYPE_SIZES: &[u64] = &[1, 1, 2, 4, 8, 1, 1, 2, 4, 8, 4, 8];
+
+const JPEG_COMPRESSION: u32 = 6;
+const DESCRIPTION: &[u8] = b"mean_image\0";
+// 2^30 values of 1 byte each
+const HUGE_COUNT: u32 = 0x4000_0000;
+// Thumbnails have to fit into a single APP1 segment
+const THUMBNAIL_SIZE: u32 = 160;
+pub(crate) const HUGE_THUMBNAIL_DIMENSION: u16 = u16::max_value();
+
+// Usually the MD5 digest of the extended XMP, which never arrives completely
+const EXTENDED_XMP_GUID: &str = "0123456789ABCDEF0123456789ABCDEF";
+const HUGE_XMP_SIZE: u32 = u32::max_value();
+
+/// Entry of an Image File Directory. The value either fits into four bytes
+/// or is the offset of the values.
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    value: u32,
+}
+
+/// TIFF structure consisting of IFD0, the Exif IFD and for thumbnails IFD1
+pub(crate) fn create_exif(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, mode: Mode) -> Result<Vec<u8>> {
+    let thumbnail = if mode == Mode::ThumbnailDimensions {
+        Some(create_thumbnail(image)?)
+    } else {
+        None
+    };
+    let exif_ifd = IFD0 + ifd_size(2);
+    let ifd1 = exif_ifd + ifd_size(1);
+    let description = if thumbnail.is_some() {
+        ifd1 + ifd_size(5)
+    } else {
+        ifd1
+    };
+
+    let mut description_field = Field {
+        tag: IMAGE_DESCRIPTION,
+        field_type: ASCII,
+        count: DESCRIPTION.len() as u32,
+        value: description,
+    };
+    let mut exif_pointer = Field {
+        tag: EXIF_IFD_POINTER,
+        field_type: LONG,
+        count: 1,
+        value: exif_ifd,
+    };
+    let mut version = Field {
+        tag: EXIF_VERSION,
+        field_type: UNDEFINED,
+        count: 4,
+        value: u32::from_be_bytes(*b"0232"),
+    };
+    let next_ifd = match mode {
+        Mode::ExifCyclicIfd => {
+            exif_pointer.value = IFD0;
+            IFD0
+        }
+        Mode::ExifTagCount => {
+            description_field.count = HUGE_COUNT;
+            version.count = HUGE_COUNT;
+            version.value = description;
+            0
+        }
+        Mode::ThumbnailDimensions => ifd1,
+        _ => bail!("Mode does not add EXIF metadata"),
+    };
+
+    let mut output = TIFF_HEADER.to_vec();
+    write_ifd(&mut output, &[description_field, exif_pointer], next_ifd);
+    write_ifd(&mut output, &[version], 0);
+    if let Some(thumbnail) = &thumbnail {
+        let dimension = u32::from(HUGE_THUMBNAIL_DIMENSION);
+        let field = |tag, field_type, value| Field {
+            tag,
+            field_type,
+            count: 1,
+            value,
+        };
+        write_ifd(
+            &mut output,
+            &[
+                field(IMAGE_WIDTH, LONG, dimension),
+                field(IMAGE_LENGTH, LONG, dimension),
+                field(COMPRESSION, SHORT, JPEG_COMPRESSION),
+                field(
+                    JPEG_INTERCHANGE_FORMAT,
+                    LONG,
+                    description + DESCRIPTION.len() as u32,
+                ),
+                field(JPEG_INTERCHANGE_FORMAT_LENGTH, LONG, thumbnail.len() as u32),
+            ],
+            0,
+        );
+    }
+    output.extend_from_slice(DESCRIPTION);
+    if let Some(thumbnail) = thumbnail {
+        output.extend(thumbnail);
+    }
+    Ok(output)
+}
+
+fn ifd_size(count: u32) -> u32 {
+    2 + count * ENTRY_SIZE + 4
+}
+
+fn write_ifd(output: &mut Vec<u8>, fields: &[Field], next_ifd: u32) {
+    output.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+    for field in fields {
+        output.extend_from_slice(&field.tag.to_be_bytes());
+        output.extend_from_slice(&field.field_type.to_be_bytes());
+        output.extend_from_slice(&field.count.to_be_bytes());
+        // inline values are left aligned
+        if field.field_type == SHORT && field.count == 1 {
+            output.extend_from_slice(&(field.value as u16).to_be_bytes());
+            output.extend_from_slice(&[0, 0]);
+        } else {
+            output.extend_from_slice(&field.value.to_be_bytes());
+        }
+    }
+    output.extend_from_slice(&next_ifd.to_be_bytes());
+}
+
+/// Scaled down baseline JPEG whose SOF claims 65535x65535 pixels
+fn create_thumbnail(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let longest = image.width().max(image.height()).max(THUMBNAIL_SIZE);
+    let width = (image.width() * THUMBNAIL_SIZE / longest).max(1);
+    let height = (image.height() * THUMBNAIL_SIZE / longest).max(1);
+    let mut thumbnail = jpeg::create_jpeg(&imageops::thumbnail(image, width, height))?;
+    jpeg::modify_width_and_height(
+        &mut thumbnail,
+        HUGE_THUMBNAIL_DIMENSION,
+        HUGE_THUMBNAIL_DIMENSION,
+    )?;
+    Ok(thumbnail)
+}
+
+fn read_fields(exif: &[u8], ifd: u32) -> Result<Vec<Field>> {
+    let ifd = ifd as usize;
+    if ifd + 2 > exif.len() {
+        bail!("Unable to find Image File Directory");
+    }
+    let count = exif_u16(exif, ifd) as u32;
+    if ifd + ifd_size(count) as usize > exif.len() {
+        bail!("Image File Directory exceeds the EXIF data");
+    }
+    Ok((0..count as usize)
+        .map(|index| {
+            let pos = ifd + 2 + index * ENTRY_SIZE as usize;
+            let field_type = exif_u16(exif, pos + 2);
+            let count = exif_u32(exif, pos + 4);
+            let value = if field_type == SHORT && count == 1 {
+                exif_u16(exif, pos + 8).into()
+            } else {
+                exif_u32(exif, pos + 8)
+            };
+            Field {
+                tag: exif_u16(exif, pos),
+                field_type,
+                count,
+                value,
+            }
+        })
+        .collect())
+}
+
+/// EXIF written by mean_image is big endian, other files may use little
+/// endian
+fn exif_u16(exif: &[u8], pos: usize) -> u16 {
+    if exif.starts_with(LITTLE_ENDIAN) {
+        extract_u16_le(exif, pos)
+    } else {
+        extract_u16(exif, pos)
+    }
+}
+
+fn exif_u32(exif: &[u8], pos: usize) -> u32 {
+    if exif.starts_with(LITTLE_ENDIAN) {
+        extract_u32_le(exif, pos)
+    } else {
+        extract_u32(exif, pos)
+    }
+}
+
+fn find_field(exif: &[u8], ifd: u32, tag: u16) -> Result<Field> {
+    read_fields(exif, ifd)?
+        .into_iter()
+        .find(|field| field.tag == tag)
+        .with_context(|| format!("Unable to find Tag {}", tag))
+}
+
+fn next_ifd(exif: &[u8], ifd: u32) -> Result<u32> {
+    let count = read_fields(exif, ifd)?.len() as u32;
+    Ok(exif_u32(exif, (ifd + ifd_size(count) - 4) as usize))
+}
+
+pub(crate) fn verify_exif(exif: &[u8], mode: Mode) -> Result<()> {
+    assert_eq!(
+        TIFF_HEADER,
+        &exif[..TIFF_HEADER.len()],
+        "EXIF TIFF header is not valid"
+    );
+    let exif_pointer = find_field(exif, IFD0, EXIF_IFD_POINTER)?;
+    let next_ifd = next_ifd(exif, IFD0)?;
+    match mode {
+        Mode::ExifCyclicIfd => {
+            assert_eq!(IFD0, next_ifd, "Next IFD offset is invalid");
+            assert_eq!(IFD0, exif_pointer.value, "Exif IFD offset is invalid");
+        }
+        Mode::ExifTagCount => {
+            let description = find_field(exif, IFD0, IMAGE_DESCRIPTION)?;
+            let version = find_field(exif, exif_pointer.value, EXIF_VERSION)?;
+            assert_eq!(HUGE_COUNT, description.count, "Tag count is invalid");
+            assert_eq!(HUGE_COUNT, version.count, "Tag count is invalid");
+        }
+        Mode::ThumbnailDimensions => {
+            let dimension = u32::from(HUGE_THUMBNAIL_DIMENSION);
+            for tag in &[IMAGE_WIDTH, IMAGE_LENGTH] {
+                let field = find_field(exif, next_ifd, *tag)?;
+                assert_eq!(dimension, field.value, "Thumbnail dimension is invalid");
+            }
+            let offset = find_field(exif, next_ifd, JPEG_INTERCHANGE_FORMAT)?.value as usize;
+            let length = find_field(exif, next_ifd, JPEG_INTERCHANGE_FORMAT_LENGTH)?.value;
+            let thumbnail = exif
+                .get(offset..offset + length as usize)
+                .context("Thumbnail exceeds the EXIF data")?;
+            jpeg::verify_image(
+                thumbnail,
+                HUGE_THUMBNAIL_DIMENSION,
+                HUGE_THUMBNAIL_DIMENSION,
+            )?;
+        }
+        _ => bail!("Mode does not add EXIF metadata"),
+    }
+    Ok(())
+}
+
+/// Follows every IFD and reports cycles, fields claiming more values than
+/// the EXIF data holds and thumbnails claiming more pixels than they contain
+pub(crate) fn inspect_exif(exif: &[u8], report: &mut Report) {
+    if exif.len() < TIFF_HEADER.len()
+        || (exif[..2] != TIFF_HEADER[..2] && &exif[..2] != LITTLE_ENDIAN)
+    {
+        report.anomaly("EXIF TIFF header is not valid");
+        return;
+    }
+    let mut visited = Vec::new();
+    let mut pending = vec![exif_u32(exif, 4)];
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 {
+            continue;
+        }
+        if visited.contains(&ifd) {
+            report.anomaly(format!("EXIF IFD at {} is referenced again", ifd));
+            continue;
+        }
+        visited.push(ifd);
+        let fields = match read_fields(exif, ifd) {
+            Ok(fields) => fields,
+            Err(error) => {
+                report.anomaly(format!("{:#}", error));
+                continue;
+            }
+        };
+        for field in &fields {
+            let size = field
+                .field_type
+                .checked_sub(1)
+                .and_then(|index| TYPE_SIZES.get(index as usize))
+                .unwrap_or(&1);
+            if field.count as u64 * size > exif.len() as u64 {
+                report.anomaly(format!(
+                    "EXIF tag {:#06x} claims {} values, more than the EXIF data holds",
+                    field.tag, field.count
+                ));
+            }
+            if field.tag == EXIF_IFD_POINTER {
+                pending.push(field.value);
+            }
+        }
+        pending.push(exif_u32(
+            exif,
+            (ifd + ifd_size(fields.len() as u32) - 4) as usize,
+        ));
+        let find = |tag| fields.iter().find(|field| field.tag == tag);
+        if let (Some(offset), Some(length)) = (
+            find(JPEG_INTERCHANGE_FORMAT),
+            find(JPEG_INTERCHANGE_FORMAT_LENGTH),
+        ) {
+            let (offset, length) = (offset.value as usize, length.value as usize);
+            match exif.get(offset..offset + length) {
+                Some(thumbnail) => inspect_thumbnail(thumbnail, report),
+                None => report.anomaly("EXIF thumbnail exceeds the EXIF data"),
+            }
+        }
+    }
+    report.field("EXIF IFDs", visited.len());
+}
+
+fn inspect_thumbnail(thumbnail: &[u8], report: &mut Report) {
+    let mut thumbnail_report = Report::default();
+    jpeg::inspect(thumbnail, &mut thumbnail_report);
+    if let Some((width, height)) = thumbnail_report.claimed {
+        report.field("EXIF thumbnail", format!("{}x{}", width, height));
+        if thumbnail_report
+            .plausible
+            .map_or(false, |plausible| width * height > plausible)
+        {
+            report.anomaly(format!(
+                "EXIF thumbnail of {}x{} claims more pixels than its image data holds",
+                width, height
+            ));
+        }
+    }
+}
+
+/// Compares the total size the extended XMP claims to the file
+pub(crate) fn inspect_extended_xmp(data: &[u8], file_size: usize, report: &mut Report) {
+    let pos = EXTENDED_XMP_GUID.len();
+    if data.len() < pos + 8 {
+        report.anomaly("Extended XMP header exceeds the segment");
+        return;
+    }
+    let size = extract_u32(data, pos);
+    report.field("Extended XMP size", size);
+    if size as usize > file_size {
+        report.anomaly(format!(
+            "Extended XMP claims {} bytes, more than the file holds",
+            size
+        ));
+    }
+}
+
+/// XMP packet announcing extended XMP, which JPEG stores in further APP1
+/// segments
+pub(crate) fn xmp_packet() -> Vec<u8> {
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:xmpNote=\"http://ns.adobe.com/xmp/note/\" ",
+            "xmpNote:HasExtendedXMP=\"{}\"/>",
+            "</rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+        ),
+        EXTENDED_XMP_GUID
+    )
+    .into_bytes()
+}
+
+/// First and only part of the extended XMP, which claims a total size of
+/// 4 GiB
+pub(crate) fn extended_xmp() -> Vec<u8> {
+    let mut output = EXTENDED_XMP_NAMESPACE.to_vec();
+    output.extend_from_slice(EXTENDED_XMP_GUID.as_bytes());
+    output.extend_from_slice(&HUGE_XMP_SIZE.to_be_bytes());
+    // offset of this part
+    output.extend_from_slice(&0_u32.to_be_bytes());
+    output.extend_from_slice(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">");
+    output
+}
+
+/// Verifies the extended XMP following its namespace
+pub(crate) fn verify_extended_xmp(data: &[u8]) {
+    let size = EXTENDED_XMP_GUID.len();
+    assert_eq!(
+        EXTENDED_XMP_GUID.as_bytes(),
+        &data[..size],
+        "Extended XMP GUID is invalid"
+    );
+    assert_eq!(
+        HUGE_XMP_SIZE,
+        extract_u32(data, size),
+        "Extended XMP size is invalid"
+    );
+}
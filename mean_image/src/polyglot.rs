@@ -0,0 +1,271 @@
+//! Polyglots, images which other parsers accept as a different format at
+//! the same time
+
+use crate::{extract_u16_le, extract_u32_le};
+use common::{bail, Context, Result};
+use crc::crc32;
+use image::ImageFormat as Decoder;
+
+const ZIP_FILE_NAME: &[u8] = b"mean_image.txt";
+const ZIP_FILE_CONTENT: &[u8] = b"mean_image\n";
+const LOCAL_FILE_HEADER: &[u8] = b"PK\x03\x04";
+const CENTRAL_DIRECTORY_HEADER: &[u8] = b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+// 1980-01-01, the earliest date ZIP can store
+const ZIP_DATE: u16 = 0x21;
+
+/// HTML document which comments out everything following it
+pub(crate) const HTML: &[u8] = b"<!DOCTYPE html><html><body><h1>mean_image</h1></body></html><!--";
+/// JavaScript between two block comments, the GIF89a signature in front of
+/// it becomes the identifier of an assignment
+pub(crate) const SCRIPT: &[u8] = b"*/=1;console.log(\"mean_image\");/*";
+pub(crate) const COMMENT_END: &[u8] = b"*/";
+
+// PDF readers look for the header and %%EOF within the first and last 1024
+// bytes
+const PDF_SEARCH_SIZE: usize = 1024;
+const PDF_OBJECTS: &[&[u8]] = &[
+    b"<</Type/Catalog/Pages 2 0 R>>",
+    b"<</Type/Pages/Kids[3 0 R]/Count 1>>",
+    b"<</Type/Page/Parent 2 0 R/MediaBox[0 0 612 792]>>",
+];
+
+/// Formats a polyglot may parse as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    JavaScript,
+    Zip,
+    Html,
+    Pdf,
+}
+
+const FORMATS: &[Format] = &[
+    Format::Png,
+    Format::Jpeg,
+    Format::Gif,
+    Format::Bmp,
+    Format::JavaScript,
+    Format::Zip,
+    Format::Html,
+    Format::Pdf,
+];
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Gif => "GIF",
+            Self::Bmp => "BMP",
+            Self::JavaScript => "JavaScript",
+            Self::Zip => "ZIP",
+            Self::Html => "HTML",
+            Self::Pdf => "PDF",
+        }
+    }
+
+    fn parses(self, data: &[u8]) -> bool {
+        let decoder = match self {
+            Self::Png => Decoder::Png,
+            Self::Jpeg => Decoder::Jpeg,
+            Self::Gif => Decoder::Gif,
+            Self::Bmp => Decoder::Bmp,
+            Self::JavaScript => return is_javascript(data),
+            Self::Zip => return is_zip(data).is_some(),
+            Self::Html => return is_html(data),
+            Self::Pdf => return is_pdf(data).is_some(),
+        };
+        image::load_from_memory_with_format(data, decoder).is_ok()
+    }
+}
+
+/// Prints every format the data parses as and verifies that the expected
+/// formats are among them
+pub(crate) fn verify_formats(data: &[u8], expected: &[Format]) -> Result<()> {
+    println!("File parses as:");
+    for &format in FORMATS {
+        let parses = format.parses(data);
+        println!("  {}: {}", format.name(), if parses { "yes" } else { "no" });
+        if expected.contains(&format) && !parses {
+            bail!("File does not parse as {}", format.name());
+        }
+    }
+    if expected.contains(&Format::JavaScript) {
+        verify_comment_ends(data)?;
+    }
+    Ok(())
+}
+
+/// Only the script and the end of the file may close a comment, any other
+/// "*/" would turn binary data into code
+fn verify_comment_ends(data: &[u8]) -> Result<()> {
+    let script = find(data, SCRIPT).context("Script is missing")?;
+    let ends: Vec<usize> = data
+        .windows(COMMENT_END.len())
+        .enumerate()
+        .filter(|(_, window)| *window == COMMENT_END)
+        .map(|(pos, _)| pos)
+        .collect();
+    if ends[..] != [script, data.len() - COMMENT_END.len()] {
+        bail!("Comments are closed within the image at {:?}", ends);
+    }
+    Ok(())
+}
+
+pub(crate) fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+/// Appends a ZIP archive containing a single stored file. Its offsets start
+/// at the beginning of the image, so ZIP readers find the archive from its
+/// end.
+pub(crate) fn append_zip(image: &[u8]) -> Vec<u8> {
+    let checksum = crc32::checksum_ieee(ZIP_FILE_CONTENT);
+    let size = ZIP_FILE_CONTENT.len() as u32;
+    // version 1.0, no flags, stored, time and date, checksum and sizes
+    let mut fields = Vec::new();
+    fields.extend_from_slice(&10_u16.to_le_bytes());
+    fields.extend_from_slice(&[0; 6]);
+    fields.extend_from_slice(&ZIP_DATE.to_le_bytes());
+    fields.extend_from_slice(&checksum.to_le_bytes());
+    fields.extend_from_slice(&size.to_le_bytes());
+    fields.extend_from_slice(&size.to_le_bytes());
+    fields.extend_from_slice(&(ZIP_FILE_NAME.len() as u16).to_le_bytes());
+    // no extra field
+    fields.extend_from_slice(&[0; 2]);
+
+    let mut output = image.to_vec();
+    let local = output.len() as u32;
+    output.extend_from_slice(LOCAL_FILE_HEADER);
+    output.extend_from_slice(&fields);
+    output.extend_from_slice(ZIP_FILE_NAME);
+    output.extend_from_slice(ZIP_FILE_CONTENT);
+
+    let central = output.len() as u32;
+    output.extend_from_slice(CENTRAL_DIRECTORY_HEADER);
+    // made by version 2.0
+    output.extend_from_slice(&20_u16.to_le_bytes());
+    output.extend_from_slice(&fields);
+    // no comment, disk 0, no attributes
+    output.extend_from_slice(&[0; 10]);
+    output.extend_from_slice(&local.to_le_bytes());
+    output.extend_from_slice(ZIP_FILE_NAME);
+
+    let central_size = output.len() as u32 - central;
+    output.extend_from_slice(END_OF_CENTRAL_DIRECTORY);
+    // disk 0, one entry on this and all disks
+    output.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    output.extend_from_slice(&central_size.to_le_bytes());
+    output.extend_from_slice(&central.to_le_bytes());
+    // no comment
+    output.extend_from_slice(&[0; 2]);
+    output
+}
+
+/// PDF placed at `offset` and the part following it at the end of the
+/// file, which points back to its cross-reference table
+pub(crate) fn pdf(offset: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut body = b"%PDF-1.4\n".to_vec();
+    let mut positions = Vec::new();
+    for (index, object) in PDF_OBJECTS.iter().enumerate() {
+        positions.push(offset + body.len());
+        body.extend(format!("{} 0 obj\n", index + 1).into_bytes());
+        body.extend_from_slice(object);
+        body.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = offset + body.len();
+    body.extend(format!("xref\n0 {}\n", PDF_OBJECTS.len() + 1).into_bytes());
+    // every entry takes exactly 20 bytes
+    body.extend_from_slice(b"0000000000 65535 f \n");
+    for position in positions {
+        body.extend(format!("{:010} 00000 n \n", position).into_bytes());
+    }
+    body.extend(format!("trailer\n<</Size {}/Root 1 0 R>>\n", PDF_OBJECTS.len() + 1).into_bytes());
+    let end = format!("\nstartxref\n{}\n%%EOF\n", xref).into_bytes();
+    (body, end)
+}
+
+/// Code outside of block comments only consists of printable characters.
+/// This cannot tell whether the code is valid, but binary data is never
+/// allowed.
+fn is_javascript(data: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos < data.len() {
+        if data[pos..].starts_with(b"/*") {
+            match find(&data[pos + 2..], b"*/") {
+                Some(end) => pos += end + 4,
+                None => return false,
+            }
+        } else if data[pos].is_ascii_graphic() || data[pos].is_ascii_whitespace() {
+            pos += 1;
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Walks the central directory and the local file headers it points to
+fn is_zip(data: &[u8]) -> Option<()> {
+    let end = rfind(data, END_OF_CENTRAL_DIRECTORY)?;
+    if end + 22 > data.len() {
+        return None;
+    }
+    let count = extract_u16_le(data, end + 10);
+    let mut pos = extract_u32_le(data, end + 16) as usize;
+    for _ in 0..count {
+        if !data
+            .get(pos..pos + 46)?
+            .starts_with(CENTRAL_DIRECTORY_HEADER)
+        {
+            return None;
+        }
+        let local = extract_u32_le(data, pos + 42) as usize;
+        if !data.get(local..)?.starts_with(LOCAL_FILE_HEADER) {
+            return None;
+        }
+        let name = extract_u16_le(data, pos + 28) as usize;
+        let extra = extract_u16_le(data, pos + 30) as usize;
+        let comment = extract_u16_le(data, pos + 32) as usize;
+        pos += 46 + name + extra + comment;
+    }
+    Some(())
+}
+
+/// Browsers render anything containing a html element, when the file is
+/// served as HTML
+fn is_html(data: &[u8]) -> bool {
+    let data = data.to_ascii_lowercase();
+    find(&data, b"<html").is_some() && find(&data, b"</html>").is_some()
+}
+
+/// Follows startxref to the cross-reference table
+fn is_pdf(data: &[u8]) -> Option<()> {
+    let start = &data[..data.len().min(PDF_SEARCH_SIZE)];
+    let end = &data[data.len().saturating_sub(PDF_SEARCH_SIZE)..];
+    if find(start, b"%PDF-").is_none() {
+        return None;
+    }
+    let startxref = rfind(end, b"startxref")? + b"startxref".len();
+    let eof = rfind(end, b"%%EOF")?;
+    let xref: usize = std::str::from_utf8(end.get(startxref..eof)?)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    if data.get(xref..)?.starts_with(b"xref") {
+        Some(())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,342 @@
+//! Progressive JPEG encoder, as the image crate only supports baseline
+//! JPEGs. Scans only use spectral selection, so each of them is coded like
+//! the corresponding part of a baseline scan.
+
+use crate::jpeg::{write_segment, APP0, DHT, DQT, EOI, SOI, SOS};
+use common::Result;
+use image::{ImageBuffer, Rgb};
+use std::f64::consts::PI;
+
+pub(crate) const SOF2: u8 = 0xc2;
+
+pub(crate) const COMPONENTS: usize = 3;
+const BLOCK_SIZE: usize = 8;
+const END_OF_BAND: u8 = 0x00;
+const ZERO_RUN: u8 = 0xf0;
+
+// Tables of section K.1 and K.3
+#[rustfmt::skip]
+const LUMA_QUANTIZATION: [u8; 64] = [
+    16, 11, 10, 16,  24,  40,  51,  61,
+    12, 12, 14, 19,  26,  58,  60,  55,
+    14, 13, 16, 24,  40,  57,  69,  56,
+    14, 17, 22, 29,  51,  87,  80,  62,
+    18, 22, 37, 56,  68, 109, 103,  77,
+    24, 35, 55, 64,  81, 104, 113,  92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103,  99,
+];
+
+#[rustfmt::skip]
+const CHROMA_QUANTIZATION: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+const LUMA_DC_CODE_LENGTHS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const CHROMA_DC_CODE_LENGTHS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const LUMA_AC_CODE_LENGTHS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const CHROMA_AC_CODE_LENGTHS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Position of the coefficients in zigzag order within a block
+#[rustfmt::skip]
+const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Scan over a band of coefficients in zigzag order. The DC coefficient has
+/// to be in a scan of its own and scans over AC coefficients may only
+/// contain one component.
+#[derive(Debug, Clone)]
+pub(crate) struct Scan {
+    pub(crate) components: Vec<usize>,
+    pub(crate) start: u8,
+    pub(crate) end: u8,
+}
+
+/// Huffman code of every symbol as code and length
+type Codes = [(u16, u8); 256];
+
+/// Writes bits most significant first and stuffs a 0 after every 0xff
+#[derive(Debug, Default)]
+struct EntropyWriter {
+    data: Vec<u8>,
+    buffer: u8,
+    used: u8,
+}
+
+impl EntropyWriter {
+    fn write(&mut self, value: u16, bits: u8) {
+        for bit in (0..bits).rev() {
+            self.buffer = self.buffer << 1 | ((value >> bit) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.data.push(self.buffer);
+                if self.buffer == 0xff {
+                    self.data.push(0);
+                }
+                self.buffer = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with 1 bits
+    fn finish(mut self) -> Vec<u8> {
+        while self.used != 0 {
+            self.write(1, 1);
+        }
+        self.data
+    }
+}
+
+/// DC scan of all components followed by two AC scans per component
+pub(crate) fn default_scans() -> Vec<Scan> {
+    let mut scans = vec![Scan {
+        components: (0..COMPONENTS).collect(),
+        start: 0,
+        end: 0,
+    }];
+    for &(start, end) in &[(1, 5), (6, 63)] {
+        for component in 0..COMPONENTS {
+            scans.push(Scan {
+                components: vec![component],
+                start,
+                end,
+            });
+        }
+    }
+    scans
+}
+
+pub(crate) fn encode(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, scans: &[Scan]) -> Result<Vec<u8>> {
+    let blocks = transform(image);
+    let dc_codes = [
+        codes(&LUMA_DC_CODE_LENGTHS, &DC_VALUES),
+        codes(&CHROMA_DC_CODE_LENGTHS, &DC_VALUES),
+    ];
+    let ac_codes = [
+        codes(&LUMA_AC_CODE_LENGTHS, &LUMA_AC_VALUES),
+        codes(&CHROMA_AC_CODE_LENGTHS, &CHROMA_AC_VALUES),
+    ];
+
+    let mut output = vec![0xff, SOI];
+    write_segment(&mut output, APP0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0")?;
+
+    let mut quantization = Vec::new();
+    for (index, table) in [LUMA_QUANTIZATION, CHROMA_QUANTIZATION].iter().enumerate() {
+        quantization.push(index as u8);
+        quantization.extend(ZIGZAG.iter().map(|&pos| table[pos]));
+    }
+    write_segment(&mut output, DQT, &quantization)?;
+
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(image.height() as u16).to_be_bytes());
+    frame.extend_from_slice(&(image.width() as u16).to_be_bytes());
+    frame.push(COMPONENTS as u8);
+    for component in 0..COMPONENTS {
+        frame.extend_from_slice(&[component as u8 + 1, 0x11, table(component)]);
+    }
+    write_segment(&mut output, SOF2, &frame)?;
+
+    let mut huffman = Vec::new();
+    for &(class, lengths, values) in &[
+        (0x00, &LUMA_DC_CODE_LENGTHS, &DC_VALUES[..]),
+        (0x01, &CHROMA_DC_CODE_LENGTHS, &DC_VALUES[..]),
+        (0x10, &LUMA_AC_CODE_LENGTHS, &LUMA_AC_VALUES[..]),
+        (0x11, &CHROMA_AC_CODE_LENGTHS, &CHROMA_AC_VALUES[..]),
+    ] {
+        huffman.push(class);
+        huffman.extend_from_slice(lengths);
+        huffman.extend_from_slice(values);
+    }
+    write_segment(&mut output, DHT, &huffman)?;
+
+    for scan in scans {
+        let mut header = vec![scan.components.len() as u8];
+        for &component in &scan.components {
+            let table = table(component);
+            header.extend_from_slice(&[component as u8 + 1, table << 4 | table]);
+        }
+        header.extend_from_slice(&[scan.start, scan.end, 0]);
+        write_segment(&mut output, SOS, &header)?;
+
+        let mut writer = EntropyWriter::default();
+        if scan.start == 0 {
+            let mut predictions = [0; COMPONENTS];
+            for index in 0..blocks[0].len() {
+                for &component in &scan.components {
+                    let value = blocks[component][index][0];
+                    let difference = value - predictions[component];
+                    predictions[component] = value;
+                    let (size, bits) = magnitude(difference);
+                    write_code(&mut writer, &dc_codes[table(component) as usize], size);
+                    writer.write(bits, size);
+                }
+            }
+        } else {
+            let codes = &ac_codes[table(scan.components[0]) as usize];
+            for block in &blocks[scan.components[0]] {
+                encode_band(&mut writer, codes, block, scan);
+            }
+        }
+        output.extend(writer.finish());
+    }
+    output.extend_from_slice(&[0xff, EOI]);
+    Ok(output)
+}
+
+/// Luma uses the first tables, chroma the second ones
+fn table(component: usize) -> u8 {
+    (component != 0) as u8
+}
+
+fn codes(lengths: &[u8; 16], values: &[u8]) -> Codes {
+    let mut codes = [(0, 0); 256];
+    let mut code = 0_u16;
+    let mut values = values.iter();
+    for (length, &count) in (1..).zip(lengths) {
+        for value in values.by_ref().take(count as usize) {
+            codes[*value as usize] = (code, length);
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+fn write_code(writer: &mut EntropyWriter, codes: &Codes, symbol: u8) {
+    let (code, length) = codes[symbol as usize];
+    writer.write(code, length);
+}
+
+/// Size category and additional bits of a coefficient
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = (32 - value.abs().leading_zeros()) as u8;
+    let bits = if value < 0 {
+        value + (1 << size) - 1
+    } else {
+        value
+    };
+    (size, bits as u16)
+}
+
+fn encode_band(writer: &mut EntropyWriter, codes: &Codes, block: &[i32; 64], scan: &Scan) {
+    let mut run = 0;
+    for &value in &block[scan.start as usize..=scan.end as usize] {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run >= 16 {
+            write_code(writer, codes, ZERO_RUN);
+            run -= 16;
+        }
+        let (size, bits) = magnitude(value);
+        write_code(writer, codes, run << 4 | size);
+        writer.write(bits, size);
+        run = 0;
+    }
+    if run > 0 {
+        write_code(writer, codes, END_OF_BAND);
+    }
+}
+
+/// Quantized coefficients in zigzag order of every block per component.
+/// The image is extended to full blocks by repeating its edges.
+fn transform(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Vec<Vec<[i32; 64]>> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let columns = (width + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    let rows = (height + BLOCK_SIZE - 1) / BLOCK_SIZE;
+
+    let mut cosines = [[0.0; BLOCK_SIZE]; BLOCK_SIZE];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, cosine) in row.iter_mut().enumerate() {
+            *cosine = ((2 * x + 1) as f64 * u as f64 * PI / 16.0).cos();
+        }
+    }
+
+    let mut blocks = vec![Vec::with_capacity(columns * rows); COMPONENTS];
+    for row in 0..rows {
+        for column in 0..columns {
+            let mut samples = [[[0.0; BLOCK_SIZE]; BLOCK_SIZE]; COMPONENTS];
+            for y in 0..BLOCK_SIZE {
+                for x in 0..BLOCK_SIZE {
+                    let pixel_x = (column * BLOCK_SIZE + x).min(width - 1);
+                    let pixel_y = (row * BLOCK_SIZE + y).min(height - 1);
+                    let [red, green, blue] = image.get_pixel(pixel_x as u32, pixel_y as u32).0;
+                    let (red, green, blue) = (red as f64, green as f64, blue as f64);
+                    // level shifted YCbCr
+                    samples[0][y][x] = 0.299 * red + 0.587 * green + 0.114 * blue - 128.0;
+                    samples[1][y][x] = -0.168_736 * red - 0.331_264 * green + 0.5 * blue;
+                    samples[2][y][x] = 0.5 * red - 0.418_688 * green - 0.081_312 * blue;
+                }
+            }
+            for (component, samples) in samples.iter().enumerate() {
+                let quantization = if component == 0 {
+                    &LUMA_QUANTIZATION
+                } else {
+                    &CHROMA_QUANTIZATION
+                };
+                let mut block = [0; 64];
+                for (index, &pos) in ZIGZAG.iter().enumerate() {
+                    let (u, v) = (pos % BLOCK_SIZE, pos / BLOCK_SIZE);
+                    let mut sum = 0.0;
+                    for y in 0..BLOCK_SIZE {
+                        for x in 0..BLOCK_SIZE {
+                            sum += samples[y][x] * cosines[x][u] * cosines[y][v];
+                        }
+                    }
+                    let scale_u = if u == 0 { 0.5_f64.sqrt() } else { 1.0 };
+                    let scale_v = if v == 0 { 0.5_f64.sqrt() } else { 1.0 };
+                    let coefficient = sum * scale_u * scale_v / 4.0;
+                    block[index] = (coefficient / quantization[pos] as f64).round() as i32;
+                }
+                blocks[component].push(block);
+            }
+        }
+    }
+    blocks
+}
@@ -0,0 +1,287 @@
+use crate::{
+    args::{Args, Mode},
+    extract_u16, extract_u16_le, extract_u32, extract_u32_le, write_u16, write_u16_le, write_u32,
+    write_u32_le,
+};
+use common::{bail, Context, Result};
+use image::{tiff::TiffEncoder, EncodableLayout, ImageBuffer, Pixel, Rgb};
+use std::{fs, io::Cursor};
+
+pub(crate) const MODES: &[Mode] = &[
+    Mode::Dimensions,
+    Mode::StripByteCounts,
+    Mode::CyclicIfd,
+    Mode::TagCount,
+];
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const STRIP_OFFSETS: u16 = 273;
+const STRIP_BYTE_COUNTS: u16 = 279;
+
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+
+// 2^30 values of 4 bytes each
+const HUGE_COUNT: u32 = 0x4000_0000;
+
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn read_u16(self, data: &[u8], start: usize) -> u16 {
+        match self {
+            Self::Little => extract_u16_le(data, start),
+            Self::Big => extract_u16(data, start),
+        }
+    }
+
+    fn read_u32(self, data: &[u8], start: usize) -> u32 {
+        match self {
+            Self::Little => extract_u32_le(data, start),
+            Self::Big => extract_u32(data, start),
+        }
+    }
+
+    fn write_u16(self, data: &mut [u8], start: usize, val: u16) {
+        match self {
+            Self::Little => write_u16_le(data, start, val),
+            Self::Big => write_u16(data, start, val),
+        }
+    }
+
+    fn write_u32(self, data: &mut [u8], start: usize, val: u32) {
+        match self {
+            Self::Little => write_u32_le(data, start, val),
+            Self::Big => write_u32(data, start, val),
+        }
+    }
+}
+
+/// Entry of an Image File Directory
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    pos: usize,
+    tag: u16,
+    field_type: u16,
+    count: u32,
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let mut image = create_tiff(image)?;
+    let (width, height) = (args.width.into(), args.height.into());
+    verify_image(&image, width, height)?;
+    match args.mode {
+        Mode::Dimensions => {
+            let (new_width, new_height) = args.new_dimensions_u32();
+            modify_width_and_height(&mut image, new_width, new_height)?;
+            verify_image(&image, new_width, new_height)?;
+        }
+        Mode::StripByteCounts => {
+            modify_strip_byte_counts(&mut image)?;
+            verify_image(&image, width, height)?;
+            verify_strip_byte_counts(&image)?;
+        }
+        Mode::CyclicIfd => {
+            make_ifd_cyclic(&mut image)?;
+            verify_image(&image, width, height)?;
+            verify_cyclic_ifd(&image)?;
+        }
+        Mode::TagCount => {
+            modify_tag_counts(&mut image)?;
+            verify_image(&image, width, height)?;
+            verify_tag_counts(&image)?;
+        }
+        _ => bail!("Mode is not supported by TIFF"),
+    }
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
+    Ok(())
+}
+
+fn create_tiff(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    let encoder = TiffEncoder::new(&mut output);
+    encoder
+        .encode(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            Rgb::<u8>::COLOR_TYPE,
+        )
+        .context("Cannot decode image")?;
+    Ok(output.into_inner())
+}
+
+fn byte_order(image: &[u8]) -> Result<ByteOrder> {
+    match &image[0..2] {
+        b"II" => Ok(ByteOrder::Little),
+        b"MM" => Ok(ByteOrder::Big),
+        _ => bail!("TIFF Byte Order is not valid"),
+    }
+}
+
+fn first_ifd(image: &[u8], order: ByteOrder) -> usize {
+    order.read_u32(image, 4) as usize
+}
+
+fn read_entries(image: &[u8], order: ByteOrder, ifd: usize) -> Result<Vec<Entry>> {
+    if ifd + 2 > image.len() {
+        bail!("Unable to find Image File Directory");
+    }
+    let count = order.read_u16(image, ifd) as usize;
+    if next_ifd_pos(ifd, count) + 4 > image.len() {
+        bail!("Image File Directory exceeds the file");
+    }
+    Ok((0..count)
+        .map(|index| {
+            let pos = ifd + 2 + index * 12;
+            Entry {
+                pos,
+                tag: order.read_u16(image, pos),
+                field_type: order.read_u16(image, pos + 2),
+                count: order.read_u32(image, pos + 4),
+            }
+        })
+        .collect())
+}
+
+fn next_ifd_pos(ifd: usize, count: usize) -> usize {
+    ifd + 2 + count * 12
+}
+
+fn find_entry(entries: &[Entry], tag: u16) -> Result<Entry> {
+    entries
+        .iter()
+        .find(|entry| entry.tag == tag)
+        .copied()
+        .with_context(|| format!("Unable to find Tag {}", tag))
+}
+
+/// Positions of all values of an entry. Values which fit into four bytes
+/// are stored inline, otherwise the entry points to them.
+fn value_positions(image: &[u8], order: ByteOrder, entry: Entry) -> Result<Vec<usize>> {
+    let size = match entry.field_type {
+        SHORT => 2,
+        LONG => 4,
+        _ => bail!("Tag {} has an unexpected type", entry.tag),
+    };
+    let length = entry.count as usize * size;
+    let start = if length <= 4 {
+        entry.pos + 8
+    } else {
+        order.read_u32(image, entry.pos + 8) as usize
+    };
+    if start + length > image.len() {
+        bail!("Values of Tag {} exceed the file", entry.tag);
+    }
+    Ok((0..entry.count as usize)
+        .map(|index| start + index * size)
+        .collect())
+}
+
+fn read_value(image: &[u8], order: ByteOrder, entry: Entry, pos: usize) -> u32 {
+    if entry.field_type == SHORT {
+        order.read_u16(image, pos) as u32
+    } else {
+        order.read_u32(image, pos)
+    }
+}
+
+fn verify_image(image: &[u8], width: u32, height: u32) -> Result<()> {
+    let order = byte_order(image)?;
+    assert_eq!(42, order.read_u16(image, 2), "TIFF Signature is not valid");
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    for (tag, expected, message) in &[
+        (IMAGE_WIDTH, width, "Image width is invalid"),
+        (IMAGE_LENGTH, height, "Image height is invalid"),
+    ] {
+        let entry = find_entry(&entries, *tag)?;
+        assert_eq!(1, entry.count, "Dimension count is invalid");
+        let img_value = read_value(image, order, entry, entry.pos + 8);
+        assert_eq!(*expected, img_value, "{}", message);
+    }
+    Ok(())
+}
+
+fn modify_width_and_height(image: &mut [u8], new_width: u32, new_height: u32) -> Result<()> {
+    let order = byte_order(image)?;
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    for (tag, value) in &[(IMAGE_WIDTH, new_width), (IMAGE_LENGTH, new_height)] {
+        // SHORT cannot hold 32 bit dimensions
+        let entry = find_entry(&entries, *tag)?;
+        order.write_u16(image, entry.pos + 2, LONG);
+        order.write_u32(image, entry.pos + 4, 1);
+        order.write_u32(image, entry.pos + 8, *value);
+    }
+    Ok(())
+}
+
+fn verify_strip_byte_counts(image: &[u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    let entry = find_entry(&entries, STRIP_BYTE_COUNTS)?;
+    for pos in value_positions(image, order, entry)? {
+        let expected = if entry.field_type == SHORT {
+            u16::max_value() as u32
+        } else {
+            u32::max_value()
+        };
+        let count = read_value(image, order, entry, pos);
+        assert_eq!(expected, count, "Strip byte count is invalid");
+    }
+    Ok(())
+}
+
+fn modify_strip_byte_counts(image: &mut [u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    let entry = find_entry(&entries, STRIP_BYTE_COUNTS)?;
+    for pos in value_positions(image, order, entry)? {
+        if entry.field_type == SHORT {
+            order.write_u16(image, pos, u16::max_value());
+        } else {
+            order.write_u32(image, pos, u32::max_value());
+        }
+    }
+    Ok(())
+}
+
+fn verify_cyclic_ifd(image: &[u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let ifd = first_ifd(image, order);
+    let entries = read_entries(image, order, ifd)?;
+    let next_ifd = order.read_u32(image, next_ifd_pos(ifd, entries.len()));
+    assert_eq!(ifd as u32, next_ifd, "Next IFD offset is invalid");
+    Ok(())
+}
+
+fn make_ifd_cyclic(image: &mut [u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let ifd = first_ifd(image, order);
+    let entries = read_entries(image, order, ifd)?;
+    order.write_u32(image, next_ifd_pos(ifd, entries.len()), ifd as u32);
+    Ok(())
+}
+
+fn verify_tag_counts(image: &[u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    for tag in &[STRIP_OFFSETS, STRIP_BYTE_COUNTS] {
+        let entry = find_entry(&entries, *tag)?;
+        assert_eq!(HUGE_COUNT, entry.count, "Tag count is invalid");
+    }
+    Ok(())
+}
+
+fn modify_tag_counts(image: &mut [u8]) -> Result<()> {
+    let order = byte_order(image)?;
+    let entries = read_entries(image, order, first_ifd(image, order))?;
+    for tag in &[STRIP_OFFSETS, STRIP_BYTE_COUNTS] {
+        let entry = find_entry(&entries, *tag)?;
+        order.write_u32(image, entry.pos + 4, HUGE_COUNT);
+    }
+    Ok(())
+}
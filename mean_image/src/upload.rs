@@ -0,0 +1,103 @@
+//! Posts images to the server configured in the env file, like the other
+//! tools of the tool belt do, and probes whether the server survives them
+
+use crate::args::Upload;
+use common::{
+    env::{setup_env, Env},
+    http::{self, Response, Status, RESPONSE_TIMEOUT},
+    run_async, Context, Result,
+};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const BOUNDARY: &str = "sec_tool_belt";
+const FORM_DATA: &str = "multipart/form-data; boundary=sec_tool_belt";
+
+/// Posts every file in its own request and probes `URL_RETURING_200` after
+/// each of them
+pub(crate) fn upload(files: &[PathBuf], args: &Upload) -> Result<()> {
+    setup_env()?;
+    let env = Env::new()?;
+    let url = args
+        .url
+        .clone()
+        .or_else(|| env.url_upload.clone())
+        .or_else(|| env.url_body_target.clone())
+        .unwrap_or_else(|| env.url_returning_200.clone());
+    println!("Posting to {}", url);
+    run_async(async {
+        for file in files {
+            let data = fs::read(file).context("Unable to read image file")?;
+            let content_type = content_type(file);
+            let response = if args.multipart {
+                let body = multipart_body(&args.field, file, content_type, &data);
+                http::send_post(&env, &url, FORM_DATA, None, &body).await?
+            } else {
+                http::send_post(&env, &url, content_type, None, &data).await?
+            };
+            print_response(file, response);
+            if http::is_alive(&env).await {
+                println!("{}: Server still responds", file.display());
+            } else {
+                println!(
+                    "{}: Server stopped responding after the upload. The image most likely crashed it!",
+                    file.display()
+                );
+            }
+        }
+        Ok(())
+    })
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(OsStr::to_str) {
+        Some("png") => "image/png",
+        Some("jpeg") | Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("bmp") => "image/bmp",
+        Some("tiff") | Some("tif") => "image/tiff",
+        Some("webp") => "image/webp",
+        Some("ico") | Some("cur") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A single part containing the file
+fn multipart_body(field: &str, file: &Path, content_type: &str, data: &[u8]) -> Vec<u8> {
+    let filename = file
+        .file_name()
+        .map_or_else(|| "image".into(), OsStr::to_string_lossy);
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        BOUNDARY, field, filename, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend(format!("\r\n--{}--\r\n", BOUNDARY).into_bytes());
+    body
+}
+
+fn print_response(file: &Path, response: Response) {
+    let millis = response.elapsed.as_millis();
+    match response.status {
+        Status::Code(status) => println!(
+            "{}: Server answered with status {} after {}ms",
+            file.display(),
+            status,
+            millis
+        ),
+        Status::Aborted => println!(
+            "{}: Server aborted the connection after {}ms",
+            file.display(),
+            millis
+        ),
+        Status::TimedOut => println!(
+            "{}: Server did not answer within {}s",
+            file.display(),
+            RESPONSE_TIMEOUT.as_secs()
+        ),
+    }
+}
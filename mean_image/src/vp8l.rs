@@ -0,0 +1,384 @@
+//! Lossless VP8L encoder. Pixels are predicted from their neighbours and
+//! the residuals are coded with Huffman codes and backward references to
+//! the pixels on the left and above.
+
+use crate::bits::BitWriter;
+use image::{ImageBuffer, Rgb};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+pub(crate) const SIGNATURE: u8 = 0x2f;
+
+const PREDICTOR_TRANSFORM: u32 = 0;
+const SUBTRACT_GREEN_TRANSFORM: u32 = 2;
+// a single predictor block covers 512x512 pixels
+const PREDICTOR_BITS: u32 = 9;
+// clamp(left + top - top left) for each channel
+const GRADIENT_PREDICTOR: u32 = 12;
+// prediction of the first pixel
+const BLACK: u32 = 0xff00_0000;
+
+const LITERALS: usize = 256;
+const LENGTH_CODES: usize = 24;
+const DISTANCE_CODES: usize = 40;
+// distance codes of the pixel above and the pixel on the left
+const ABOVE: u16 = 1;
+const LEFT: u16 = 2;
+// shorter matches cost about as much as their literals
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 4096;
+
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+const CODE_LENGTH_CODES: usize = 19;
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_CODES] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Symbol {
+    Literal(u32),
+    Copy { length: u16, distance_code: u16 },
+}
+
+/// Canonical prefix code and its length for each symbol
+type Codes = Vec<(u32, u32)>;
+
+/// Encodes the image using the subtract green and predictor transforms
+pub(crate) fn encode(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Vec<u8> {
+    let (width, height) = (image.width(), image.height());
+    let mut writer = BitWriter::default();
+    writer.write(SIGNATURE.into(), 8);
+    writer.write(width - 1, 14);
+    writer.write(height - 1, 14);
+    // alpha is unused, version 0
+    writer.write(0, 1);
+    writer.write(0, 3);
+
+    writer.write(1, 1);
+    writer.write(SUBTRACT_GREEN_TRANSFORM, 2);
+    writer.write(1, 1);
+    writer.write(PREDICTOR_TRANSFORM, 2);
+    writer.write(PREDICTOR_BITS - 2, 3);
+    // every block uses the gradient predictor, which is stored in green
+    let blocks = (block_count(width) * block_count(height)) as usize;
+    // no color cache
+    writer.write(0, 1);
+    write_symbols(
+        &mut writer,
+        &vec![Symbol::Literal(GRADIENT_PREDICTOR << 8); blocks],
+    );
+    // no further transforms
+    writer.write(0, 1);
+
+    // no color cache, no meta prefix codes
+    writer.write(0, 2);
+    let residuals = residuals(image);
+    write_symbols(
+        &mut writer,
+        &backward_references(&residuals, width as usize),
+    );
+    writer.into_bytes()
+}
+
+fn block_count(size: u32) -> u32 {
+    (size + (1 << PREDICTOR_BITS) - 1) >> PREDICTOR_BITS
+}
+
+/// Applies the transforms in the order the decoder reverts them
+fn residuals(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Vec<u32> {
+    let width = image.width() as usize;
+    let pixels: Vec<u32> = image
+        .pixels()
+        .map(|pixel| {
+            let [red, green, blue] = pixel.0;
+            u32::from_be_bytes([
+                0xff,
+                red.wrapping_sub(green),
+                green,
+                blue.wrapping_sub(green),
+            ])
+        })
+        .collect();
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(index, &pixel)| {
+            let prediction = match (index % width, index / width) {
+                (0, 0) => BLACK,
+                (_, 0) => pixels[index - 1],
+                (0, _) => pixels[index - width],
+                _ => gradient(
+                    pixels[index - 1],
+                    pixels[index - width],
+                    pixels[index - width - 1],
+                ),
+            };
+            map_channels(pixel, prediction, u8::wrapping_sub)
+        })
+        .collect()
+}
+
+fn gradient(left: u32, top: u32, top_left: u32) -> u32 {
+    let (left, top, top_left) = (
+        left.to_be_bytes(),
+        top.to_be_bytes(),
+        top_left.to_be_bytes(),
+    );
+    let mut output = [0; 4];
+    for (channel, output) in output.iter_mut().enumerate() {
+        let value =
+            i16::from(left[channel]) + i16::from(top[channel]) - i16::from(top_left[channel]);
+        *output = value.max(0).min(0xff) as u8;
+    }
+    u32::from_be_bytes(output)
+}
+
+fn map_channels(first: u32, second: u32, map: impl Fn(u8, u8) -> u8) -> u32 {
+    let (first, second) = (first.to_be_bytes(), second.to_be_bytes());
+    let mut output = [0; 4];
+    for (output, (&first, &second)) in output.iter_mut().zip(first.iter().zip(&second)) {
+        *output = map(first, second);
+    }
+    u32::from_be_bytes(output)
+}
+
+/// Greedily copies the longest run matching the pixels on the left or above
+fn backward_references(pixels: &[u32], width: usize) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut pos = 0;
+    while pos < pixels.len() {
+        let left = match_length(pixels, pos, 1);
+        let above = match_length(pixels, pos, width);
+        let (length, distance_code) = if above > left {
+            (above, ABOVE)
+        } else {
+            (left, LEFT)
+        };
+        if length >= MIN_MATCH {
+            symbols.push(Symbol::Copy {
+                length: length as u16,
+                distance_code,
+            });
+            pos += length;
+        } else {
+            symbols.push(Symbol::Literal(pixels[pos]));
+            pos += 1;
+        }
+    }
+    symbols
+}
+
+fn match_length(pixels: &[u32], pos: usize, distance: usize) -> usize {
+    if pos < distance {
+        return 0;
+    }
+    pixels[pos..]
+        .iter()
+        .zip(&pixels[pos - distance..])
+        .take(MAX_MATCH)
+        .take_while(|(pixel, previous)| pixel == previous)
+        .count()
+}
+
+/// Writes the five prefix codes followed by the symbols
+fn write_symbols(writer: &mut BitWriter, symbols: &[Symbol]) {
+    let mut green = vec![0; LITERALS + LENGTH_CODES];
+    let mut red = vec![0; LITERALS];
+    let mut blue = vec![0; LITERALS];
+    let mut alpha = vec![0; LITERALS];
+    let mut distance = vec![0; DISTANCE_CODES];
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(pixel) => {
+                let [a, r, g, b] = pixel.to_be_bytes();
+                green[g as usize] += 1;
+                red[r as usize] += 1;
+                blue[b as usize] += 1;
+                alpha[a as usize] += 1;
+            }
+            Symbol::Copy {
+                length,
+                distance_code,
+            } => {
+                green[LITERALS + prefix(length.into()).0] += 1;
+                distance[prefix(distance_code.into()).0] += 1;
+            }
+        }
+    }
+    let green = write_prefix_code(writer, &green);
+    let red = write_prefix_code(writer, &red);
+    let blue = write_prefix_code(writer, &blue);
+    let alpha = write_prefix_code(writer, &alpha);
+    let distance = write_prefix_code(writer, &distance);
+
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(pixel) => {
+                let [a, r, g, b] = pixel.to_be_bytes();
+                for &(codes, value) in &[(&green, g), (&red, r), (&blue, b), (&alpha, a)] {
+                    let (code, bits) = codes[value as usize];
+                    writer.write_code(code, bits);
+                }
+            }
+            Symbol::Copy {
+                length,
+                distance_code,
+            } => {
+                for &(codes, offset, value) in
+                    &[(&green, LITERALS, length), (&distance, 0, distance_code)]
+                {
+                    let (prefix, extra_bits, extra) = prefix(value.into());
+                    let (code, bits) = codes[offset + prefix];
+                    writer.write_code(code, bits);
+                    writer.write(extra, extra_bits);
+                }
+            }
+        }
+    }
+}
+
+/// Splits a length or distance code into its prefix and extra bits
+fn prefix(value: usize) -> (usize, u32, u32) {
+    let value = (value - 1) as u32;
+    if value < 4 {
+        return (value as usize, 0, 0);
+    }
+    let highest = 31 - value.leading_zeros();
+    let second = (value >> (highest - 1)) & 1;
+    let extra_bits = highest - 1;
+    (
+        (2 * highest + second) as usize,
+        extra_bits,
+        value & ((1 << extra_bits) - 1),
+    )
+}
+
+/// Writes a prefix code fitting the histogram and returns its codes
+fn write_prefix_code(writer: &mut BitWriter, histogram: &[u32]) -> Codes {
+    let used: Vec<usize> = (0..histogram.len())
+        .filter(|&symbol| histogram[symbol] != 0)
+        .collect();
+    match used[..] {
+        // a simple code with a single symbol takes no bits at all
+        [] | [0..=0xff] => {
+            let symbol = used.first().copied().unwrap_or(0) as u32;
+            writer.write(1, 1);
+            writer.write(0, 1);
+            if symbol < 2 {
+                writer.write(0, 1);
+                writer.write(symbol, 1);
+            } else {
+                writer.write(1, 1);
+                writer.write(symbol, 8);
+            }
+            vec![(0, 0); histogram.len()]
+        }
+        _ => {
+            let lengths = code_lengths(histogram, MAX_CODE_LENGTH);
+            writer.write(0, 1);
+            write_code_lengths(writer, &lengths);
+            canonical_codes(&lengths)
+        }
+    }
+}
+
+/// Writes the code lengths using the code length code
+fn write_code_lengths(writer: &mut BitWriter, lengths: &[u8]) {
+    let mut histogram = [0; CODE_LENGTH_CODES];
+    for &length in lengths {
+        histogram[length as usize] += 1;
+    }
+    let code_length_lengths = code_lengths(&histogram, MAX_CODE_LENGTH_CODE_LENGTH);
+    let count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&length| code_length_lengths[length] != 0)
+        .map_or(4, |pos| (pos + 1).max(4));
+    writer.write((count - 4) as u32, 4);
+    for &length in &CODE_LENGTH_ORDER[..count] {
+        writer.write(code_length_lengths[length].into(), 3);
+    }
+    // max_symbol is omitted, the lengths cover the whole alphabet
+    writer.write(0, 1);
+    let codes = canonical_codes(&code_length_lengths);
+    for &length in lengths {
+        let (code, bits) = codes[length as usize];
+        writer.write_code(code, bits);
+    }
+}
+
+/// Huffman code lengths, at least two symbols are used as a single symbol
+/// would take no bits in a normal code. Frequencies are halved until the
+/// code fits the limit.
+fn code_lengths(histogram: &[u32], limit: u8) -> Vec<u8> {
+    let mut weights: Vec<u64> = histogram.iter().map(|&count| count.into()).collect();
+    for pos in 0..2 {
+        if weights.iter().filter(|&&weight| weight != 0).count() < 2 && weights[pos] == 0 {
+            weights[pos] = 1;
+        }
+    }
+    loop {
+        let lengths = huffman_lengths(&weights);
+        if lengths.iter().all(|&length| length <= limit) {
+            return lengths;
+        }
+        for weight in weights.iter_mut().filter(|weight| **weight != 0) {
+            *weight = (*weight + 1) / 2;
+        }
+    }
+}
+
+fn huffman_lengths(weights: &[u64]) -> Vec<u8> {
+    let symbols: Vec<usize> = (0..weights.len())
+        .filter(|&symbol| weights[symbol] != 0)
+        .collect();
+    // the first nodes are the leaves, followed by the inner nodes
+    let mut parents = vec![None; symbols.len()];
+    let mut heap: BinaryHeap<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((weights[symbol], node)))
+        .collect();
+    while let (Some(Reverse((first, first_node))), Some(Reverse((second, second_node)))) =
+        (heap.pop(), heap.pop())
+    {
+        let node = parents.len();
+        parents.push(None);
+        parents[first_node] = Some(node);
+        parents[second_node] = Some(node);
+        heap.push(Reverse((first + second, node)));
+    }
+
+    let mut lengths = vec![0; weights.len()];
+    for (mut node, &symbol) in symbols.iter().enumerate() {
+        while let Some(parent) = parents[node] {
+            lengths[symbol] += 1;
+            node = parent;
+        }
+    }
+    lengths
+}
+
+/// Assigns codes in symbol order like Deflate
+fn canonical_codes(lengths: &[u8]) -> Codes {
+    let mut counts = [0; MAX_CODE_LENGTH as usize + 1];
+    for &length in lengths.iter().filter(|&&length| length != 0) {
+        counts[length as usize] += 1;
+    }
+    let mut next = [0; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0;
+    for bits in 1..next.len() {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return (0, 0);
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            (code, length.into())
+        })
+        .collect()
+}
@@ -0,0 +1,190 @@
+use crate::{
+    args::{Args, Mode},
+    extract_u32_le, vp8l, write_u32_le,
+};
+use common::{bail, Context, Result};
+use image::{ImageBuffer, Rgb};
+use std::fs;
+
+pub(crate) const MODES: &[Mode] = &[Mode::Dimensions, Mode::RiffSize];
+
+const RIFF_HEADER_SIZE: usize = 12;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+// VP8L stores dimensions in 14 bit
+const MAX_FRAME_SIZE: u32 = 0x4000;
+
+const INCONSISTENT_SIZE: u32 = 0xffff_fffe;
+
+/// Chunk within the RIFF container
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    pos: usize,
+    fourcc: [u8; 4],
+}
+
+impl Chunk {
+    fn data(self) -> usize {
+        self.pos + CHUNK_HEADER_SIZE
+    }
+}
+
+pub(crate) fn create_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, args: &Args) -> Result<()> {
+    let mut image = create_webp(image)?;
+    let (width, height) = (args.width.into(), args.height.into());
+    verify_image(&image, width, height)?;
+    match args.mode {
+        Mode::Dimensions => {
+            let (new_width, new_height) = args.new_dimensions_u24()?;
+            if new_width == 0 || new_height == 0 {
+                bail!("WebP cannot store dimensions of 0");
+            }
+            modify_width_and_height(&mut image, new_width, new_height)?;
+            verify_image(&image, new_width, new_height)?;
+        }
+        Mode::RiffSize => {
+            let chunks = read_chunks(&image)?;
+            modify_riff_size(&mut image, &chunks);
+            verify_riff_size(&image, &chunks);
+        }
+        _ => bail!("Mode is not supported by WebP"),
+    }
+    fs::write(args.output_path()?, image).context("Unable to write to image file")?;
+    Ok(())
+}
+
+/// Creates an extended WebP consisting of a VP8X and a lossless VP8L chunk
+fn create_webp(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        bail!("WebP cannot store dimensions of 0");
+    }
+    if width > MAX_FRAME_SIZE || height > MAX_FRAME_SIZE {
+        bail!("WebP frames cannot be larger than 16384x16384");
+    }
+
+    let mut vp8x = vec![0; 10];
+    write_u24_le(&mut vp8x, 4, width - 1);
+    write_u24_le(&mut vp8x, 7, height - 1);
+
+    let mut output = b"RIFF\0\0\0\0WEBP".to_vec();
+    write_chunk(&mut output, b"VP8X", &vp8x);
+    write_chunk(&mut output, b"VP8L", &vp8l::encode(image));
+    let riff_size = (output.len() - CHUNK_HEADER_SIZE) as u32;
+    write_u32_le(&mut output, 4, riff_size);
+    Ok(output)
+}
+
+fn write_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+fn read_chunks(image: &[u8]) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut pos = RIFF_HEADER_SIZE;
+    while pos + CHUNK_HEADER_SIZE <= image.len() {
+        let mut fourcc = [0; 4];
+        fourcc.copy_from_slice(&image[pos..pos + 4]);
+        let size = extract_u32_le(image, pos + 4);
+        let chunk = Chunk { pos, fourcc };
+        if chunk.data() + size as usize > image.len() {
+            bail!(
+                "Chunk {} exceeds the file",
+                String::from_utf8_lossy(&fourcc)
+            );
+        }
+        chunks.push(chunk);
+        pos = chunk.data() + size as usize + size as usize % 2;
+    }
+    Ok(chunks)
+}
+
+fn find_chunk(chunks: &[Chunk], fourcc: &[u8; 4]) -> Option<Chunk> {
+    chunks.iter().find(|chunk| &chunk.fourcc == fourcc).copied()
+}
+
+fn verify_image(image: &[u8], width: u32, height: u32) -> Result<()> {
+    assert_eq!(b"RIFF", &image[0..4], "RIFF Signature is not valid");
+    assert_eq!(b"WEBP", &image[8..12], "WebP Signature is not valid");
+    let riff_size = extract_u32_le(image, 4) as usize;
+    assert_eq!(
+        image.len() - CHUNK_HEADER_SIZE,
+        riff_size,
+        "RIFF size is invalid"
+    );
+
+    let chunks = read_chunks(image)?;
+    let vp8x = find_chunk(&chunks, b"VP8X").context("Unable to find VP8X chunk")?;
+    let canvas_width = extract_u24_le(image, vp8x.data() + 4) + 1;
+    let canvas_height = extract_u24_le(image, vp8x.data() + 7) + 1;
+    assert_eq!(width, canvas_width, "Canvas width is invalid");
+    assert_eq!(height, canvas_height, "Canvas height is invalid");
+
+    let (frame_width, frame_height) = (width.min(MAX_FRAME_SIZE), height.min(MAX_FRAME_SIZE));
+    if let Some(vp8l) = find_chunk(&chunks, b"VP8L") {
+        let pos = vp8l.data();
+        assert_eq!(vp8l::SIGNATURE, image[pos], "VP8L Signature is not valid");
+        let header = extract_u32_le(image, pos + 1);
+        assert_eq!(frame_width, (header & 0x3fff) + 1, "Frame width is invalid");
+        assert_eq!(
+            frame_height,
+            ((header >> 14) & 0x3fff) + 1,
+            "Frame height is invalid"
+        );
+    } else {
+        bail!("Unable to find VP8L chunk");
+    }
+    Ok(())
+}
+
+/// Writes the dimensions into the VP8X canvas and as far as they fit into
+/// the frame. All sizes stay consistent.
+fn modify_width_and_height(image: &mut [u8], new_width: u32, new_height: u32) -> Result<()> {
+    let chunks = read_chunks(image)?;
+    let vp8x = find_chunk(&chunks, b"VP8X").context("Unable to find VP8X chunk")?;
+    write_u24_le(image, vp8x.data() + 4, new_width - 1);
+    write_u24_le(image, vp8x.data() + 7, new_height - 1);
+
+    let (frame_width, frame_height) = (
+        new_width.min(MAX_FRAME_SIZE),
+        new_height.min(MAX_FRAME_SIZE),
+    );
+    let vp8l = find_chunk(&chunks, b"VP8L").context("Unable to find VP8L chunk")?;
+    let pos = vp8l.data() + 1;
+    let header = extract_u32_le(image, pos) & !0x0fff_ffff;
+    let header = header | (frame_width - 1) | ((frame_height - 1) << 14);
+    write_u32_le(image, pos, header);
+    Ok(())
+}
+
+fn verify_riff_size(image: &[u8], chunks: &[Chunk]) {
+    assert_eq!(
+        INCONSISTENT_SIZE,
+        extract_u32_le(image, 4),
+        "RIFF size is invalid"
+    );
+    for chunk in chunks {
+        let size = extract_u32_le(image, chunk.pos + 4);
+        assert_eq!(INCONSISTENT_SIZE, size, "Chunk size is invalid");
+    }
+}
+
+fn modify_riff_size(image: &mut [u8], chunks: &[Chunk]) {
+    write_u32_le(image, 4, INCONSISTENT_SIZE);
+    for chunk in chunks {
+        write_u32_le(image, chunk.pos + 4, INCONSISTENT_SIZE);
+    }
+}
+
+fn extract_u24_le(data: &[u8], start: usize) -> u32 {
+    u32::from_le_bytes([data[start], data[start + 1], data[start + 2], 0])
+}
+
+fn write_u24_le(data: &mut [u8], start: usize, val: u32) {
+    data[start..start + 3].copy_from_slice(&val.to_le_bytes()[..3]);
+}
@@ -0,0 +1,107 @@
+use crate::bits::BitWriter;
+
+const MAX_MATCH: u64 = 258;
+const ADLER_MODULUS: u64 = 65521;
+
+/// Zlib stream of `length` zero bytes with the best ratio Deflate allows.
+///
+/// A single dynamic block only knows the literal 0, the end of block and the
+/// length 258, each match of 258 bytes at distance 1 then takes 2 bits.
+pub(crate) fn zeros(length: u64) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // final block, dynamic Huffman codes
+    writer.write(1, 1);
+    writer.write(2, 2);
+    // 286 literal/length codes, 1 distance code, 18 code length codes
+    writer.write(29, 5);
+    writer.write(0, 5);
+    writer.write(14, 4);
+    // code length code lengths in the order 16, 17, 18, 0, 8, 7, 9, 6, 10, 5,
+    // 11, 4, 12, 3, 13, 2, 14, 1: 18 => `0`, 1 => `10`, 2 => `11`
+    for length in &[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2] {
+        writer.write(*length, 3);
+    }
+
+    // literal 0 => `10`
+    writer.write_code(0b11, 2);
+    // literals 1 to 255 are unused
+    writer.write_code(0b0, 1);
+    writer.write(138 - 11, 7);
+    writer.write_code(0b0, 1);
+    writer.write(117 - 11, 7);
+    // end of block => `11`
+    writer.write_code(0b11, 2);
+    // lengths 3 to 257 are unused
+    writer.write_code(0b0, 1);
+    writer.write(28 - 11, 7);
+    // length 258 => `0`
+    writer.write_code(0b10, 2);
+    // distance 1 => `0`
+    writer.write_code(0b10, 2);
+
+    if length > 0 {
+        writer.write_code(0b10, 2);
+        let matches = (length - 1) / MAX_MATCH;
+        writer.write_zeros(matches * 2);
+        for _ in 0..(length - 1) % MAX_MATCH {
+            writer.write_code(0b10, 2);
+        }
+    }
+    writer.write_code(0b11, 2);
+
+    let mut output = vec![0x78, 0x01];
+    output.extend(writer.into_bytes());
+    // sum of all bytes is 1 as every byte is 0
+    let checksum = (length % ADLER_MODULUS) << 16 | 1;
+    output.extend_from_slice(&(checksum as u32).to_be_bytes());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{zeros, ADLER_MODULUS};
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    const LENGTHS: [u64; 9] = [0, 1, 2, 258, 259, 260, 517, 65_536, 1_000_000];
+
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let _ = ZlibDecoder::new(data)
+            .read_to_end(&mut output)
+            .expect("Zlib stream is invalid");
+        output
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1, 0);
+        for &byte in data {
+            a = (a + u64::from(byte)) % ADLER_MODULUS;
+            b = (b + a) % ADLER_MODULUS;
+        }
+        ((b << 16) | a) as u32
+    }
+
+    #[test]
+    fn zeros_inflate_to_length() {
+        for &length in &LENGTHS {
+            let inflated = inflate(&zeros(length));
+            assert_eq!(length as usize, inflated.len(), "Length is invalid");
+            assert!(inflated.iter().all(|&byte| byte == 0), "Data is not zero");
+        }
+    }
+
+    #[test]
+    fn zeros_checksum() {
+        for &length in &LENGTHS {
+            let stream = zeros(length);
+            let mut checksum = [0; 4];
+            checksum.copy_from_slice(&stream[stream.len() - 4..]);
+            assert_eq!(
+                adler32(&inflate(&stream)),
+                u32::from_be_bytes(checksum),
+                "Adler-32 is invalid"
+            );
+        }
+    }
+}